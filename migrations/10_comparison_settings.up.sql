@@ -0,0 +1,11 @@
+-- json {"color_tolerance"?: number, "threshold"?: number, "method"?: "pixel" | "perceptual",
+-- "min_ssim"?: number}, what is left out falls back to the settings of the run's tags and
+-- then to the server's
+ALTER TABLE test_case ADD COLUMN comparison_settings TEXT NOT NULL DEFAULT '{}';
+
+CREATE TABLE tag_comparison_settings(
+   tag_id INTEGER NOT NULL PRIMARY KEY,
+-- json, same as test_case.comparison_settings
+   settings TEXT NOT NULL,
+   FOREIGN KEY(tag_id) REFERENCES tag(id)
+);
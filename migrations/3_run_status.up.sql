@@ -0,0 +1,7 @@
+-- in_progress | completed | failed | aborted
+ALTER TABLE run ADD COLUMN status TEXT NOT NULL DEFAULT 'in_progress';
+-- RFC 3339, set once the run leaves in_progress
+ALTER TABLE run ADD COLUMN finished_at TEXT;
+
+-- Runs that predate the lifecycle were uploaded in full already
+UPDATE run SET status = 'completed', finished_at = created_at;
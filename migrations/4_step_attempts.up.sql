@@ -0,0 +1,14 @@
+-- 1 for the first upload, bumped every time a conflicting upload keeps both images
+ALTER TABLE step ADD COLUMN attempt INTEGER NOT NULL DEFAULT 1;
+
+-- Earlier screenshots of a step, superseded by a later attempt
+CREATE TABLE step_attempt(
+   id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+   step_id INTEGER NOT NULL,
+   attempt INTEGER NOT NULL,
+   data_uri TEXT NOT NULL,
+-- RFC 3339
+   created_at TEXT NOT NULL,
+   FOREIGN KEY(step_id) REFERENCES step(id),
+   UNIQUE(step_id, attempt)
+);
@@ -0,0 +1,6 @@
+ALTER TABLE run ADD COLUMN vcs_revision TEXT;
+ALTER TABLE run ADD COLUMN branch TEXT;
+ALTER TABLE run ADD COLUMN pull_request INTEGER;
+ALTER TABLE run ADD COLUMN ci_build_url TEXT;
+-- json {[key: string]: string}, e.g. browser version and OS
+ALTER TABLE run ADD COLUMN environment TEXT NOT NULL DEFAULT '{}';
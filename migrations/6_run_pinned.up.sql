@@ -0,0 +1,2 @@
+-- Pinned runs, e.g. baselines, are never removed by retention
+ALTER TABLE run ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,16 @@
+-- Screenshots stored once no matter how many steps show them
+CREATE TABLE blob(
+-- hex SHA-256 of data
+   hash TEXT NOT NULL PRIMARY KEY,
+   mime TEXT NOT NULL,
+   data BLOB NOT NULL,
+-- RFC 3339
+   created_at TEXT NOT NULL
+);
+
+-- Replaces data_uri, which is emptied once the server moved its image into blob on startup
+ALTER TABLE step ADD COLUMN image_hash TEXT REFERENCES blob(hash);
+ALTER TABLE step_attempt ADD COLUMN image_hash TEXT REFERENCES blob(hash);
+
+CREATE INDEX step_image_hash ON step(image_hash);
+CREATE INDEX step_attempt_image_hash ON step_attempt(image_hash);
@@ -0,0 +1,8 @@
+-- Downscaled screenshots for previews, generated on first request
+CREATE TABLE thumbnail(
+   image_hash TEXT NOT NULL PRIMARY KEY REFERENCES blob(hash) ON DELETE CASCADE,
+-- PNG
+   data BLOB NOT NULL,
+-- RFC 3339
+   created_at TEXT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- json CaptureMetadata, how the screenshot was taken as reported by the uploader
+ALTER TABLE step ADD COLUMN capture TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE step_attempt ADD COLUMN capture TEXT NOT NULL DEFAULT '{}';
+
+-- Pixel size, read from the image when stored, NULL for images stored before
+ALTER TABLE blob ADD COLUMN width INTEGER;
+ALTER TABLE blob ADD COLUMN height INTEGER;
@@ -1,129 +1,385 @@
-use axum::extract::Path;
+pub mod batch;
+pub mod comparisons;
+pub mod runs;
+pub mod tags;
+pub mod test_cases;
+
+use axum::extract::DefaultBodyLimit;
 use axum::extract::State;
-use axum::http::header;
 use axum::http::HeaderMap;
 use axum::response::IntoResponse;
+use axum::response::Response;
 use axum::routing::get;
 use axum::routing::post;
+use axum::Extension;
 use axum::Json;
 use axum::Router;
 use serde::Deserialize;
 use serde::Serialize;
 use sqlx::Pool;
 use sqlx::Sqlite;
+use sqlx::SqliteConnection;
 
-use crate::db::get_step_data_uri_and_test_case_id;
+use crate::db::get_step_image_hash_and_test_case_id;
 use crate::db::get_test_case;
 use crate::db::insert_and_get_run;
 use crate::db::insert_and_get_step;
 use crate::db::insert_and_get_test_case;
+use crate::db::run_exists;
+use crate::db::test_case_exists;
+use crate::error::HttpError;
 use crate::error::HttpResult;
+use crate::error::JsonBody;
+use crate::error::MultipartBody;
+use crate::error::PathParams;
+use crate::events;
+use crate::events::Events;
+use crate::images::conditional;
+use crate::models::blob::content_hash;
+use crate::models::blob::Blob;
+use crate::models::capture_metadata::CaptureMetadata;
+use crate::models::comparison::SizeMismatch;
+use crate::models::comparison_settings::ComparisonSettings;
+use crate::models::conflict_policy::ConflictPolicy;
+use crate::models::ingest_event::IngestEvent;
+use crate::models::run_metadata::RunMetadata;
+use crate::models::step::Step;
+use crate::models::test_case::IgnoreArea;
 use crate::services::compare_steps;
+use crate::services::data_uri_to_bytes;
+use crate::services::image_mime;
+use crate::services::step_comparison_settings;
+use crate::services::validate_step_image;
+use crate::storage::Storage;
+
+/// Full-page captures easily exceed axum's default 2MB body limit
+const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
 
 #[derive(Debug, Serialize)]
 struct Comparison {
     contains_changes: bool,
+    size_mismatch: Option<SizeMismatch>,
+    ssim: Option<f64>,
 }
 
 async fn diff_steps_by_image(
     State(db): State<Pool<Sqlite>>,
-    Path(path): Path<(Option<i64>, Option<i64>)>,
-) -> HttpResult<(HeaderMap, impl IntoResponse)> {
-    let mut headers = HeaderMap::new();
-    headers.insert(header::CACHE_CONTROL, "public, max-age=31557600".parse()?);
-
+    Extension(storage): Extension<Storage>,
+    Extension(global): Extension<ComparisonSettings>,
+    PathParams(path): PathParams<(Option<i64>, Option<i64>)>,
+    headers: HeaderMap,
+) -> HttpResult<Response> {
     let (Some(left_step_id), Some(right_step_id)) = path else {
-        return Ok((
-            headers,
-            Json(Comparison {
-                contains_changes: false,
-            }),
-        ));
+        return Ok(Json(Comparison {
+            contains_changes: false,
+            size_mismatch: None,
+            ssim: None,
+        })
+        .into_response());
     };
 
-    let (left_data_uri, left_test_case_id) =
-        get_step_data_uri_and_test_case_id(left_step_id, &db).await?;
+    let (left_image_hash, left_test_case_id) =
+        get_step_image_hash_and_test_case_id(left_step_id, &db).await?;
     let left_test_case = get_test_case(&db, left_test_case_id).await?;
-    let (right_data_uri, right_test_case_id) =
-        get_step_data_uri_and_test_case_id(right_step_id, &db).await?;
+    let (right_image_hash, right_test_case_id) =
+        get_step_image_hash_and_test_case_id(right_step_id, &db).await?;
     let right_test_case = get_test_case(&db, right_test_case_id).await?;
+    let settings = step_comparison_settings(&db, global, &left_test_case, &right_test_case).await?;
     let ignore_ranges = [left_test_case.ignore_areas, right_test_case.ignore_areas].concat();
 
-    let (contains_changes, _diff_img_data_uri) = compare_steps(
-        left_data_uri.as_str(),
-        right_data_uri.as_str(),
-        &ignore_ranges,
-    )
-    .await?;
+    // Overwritten steps and changed settings both change the verdict
+    let inputs = format!(
+        "comparison:{left_image_hash}:{right_image_hash}:{}:{}",
+        serde_json::to_string(&ignore_ranges)?,
+        serde_json::to_string(&settings)?
+    );
+    let etag = format!("\"{}\"", content_hash(inputs.as_bytes()));
+
+    conditional(&headers, etag, || async {
+        let comparison = compare_steps(
+            &storage.blob(&left_image_hash).await?.data,
+            &storage.blob(&right_image_hash).await?.data,
+            &ignore_ranges,
+            &settings,
+            false,
+        )
+        .await?;
 
-    Ok((headers, Json(Comparison { contains_changes })))
+        Ok(Json(Comparison {
+            contains_changes: comparison.contains_changes,
+            size_mismatch: comparison.size_mismatch,
+            ssim: comparison.ssim.map(|ssim| ssim.score),
+        }))
+    })
+    .await
 }
 
 #[derive(Debug, Deserialize)]
 struct PostStepReqBody {
     run_id: String,
     run_tags: Vec<String>,
+    #[serde(default)]
+    run_metadata: Option<RunMetadata>,
+    #[serde(default)]
+    test_case_tags: Vec<String>,
     test_case_name: String,
     step_name: String,
+    #[serde(default)]
+    step_tags: Vec<String>,
     img_base64_url: String,
+    #[serde(default)]
+    capture: CaptureMetadata,
+    parent_step_id: Option<i64>,
+    ignore_areas: Vec<IgnoreArea>,
+    #[serde(default)]
+    comparison_settings: ComparisonSettings,
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+}
+
+/// A step as [`insert_step`] takes it, with the screenshot already decoded by whichever
+/// route it came through
+struct NewStep {
+    run_id: String,
+    run_tags: Vec<String>,
+    run_metadata: Option<RunMetadata>,
+    test_case_tags: Vec<String>,
+    test_case_name: String,
+    step_name: String,
+    step_tags: Vec<String>,
+    image: Blob,
+    capture: CaptureMetadata,
     parent_step_id: Option<i64>,
-    ignore_areas: Vec<((u32, u32), (u32, u32))>,
+    ignore_areas: Vec<IgnoreArea>,
+    comparison_settings: ComparisonSettings,
+    on_conflict: ConflictPolicy,
+}
+
+impl TryFrom<PostStepReqBody> for NewStep {
+    type Error = HttpError;
+
+    fn try_from(body: PostStepReqBody) -> HttpResult<NewStep> {
+        let (mime, data) = data_uri_to_bytes(&body.img_base64_url)?;
+        Ok(NewStep {
+            run_id: body.run_id,
+            run_tags: body.run_tags,
+            run_metadata: body.run_metadata,
+            test_case_tags: body.test_case_tags,
+            test_case_name: body.test_case_name,
+            step_name: body.step_name,
+            step_tags: body.step_tags,
+            image: Blob::new(mime, data),
+            capture: body.capture,
+            parent_step_id: body.parent_step_id,
+            ignore_areas: body.ignore_areas,
+            comparison_settings: body.comparison_settings,
+            on_conflict: body.on_conflict,
+        })
+    }
 }
 
 #[derive(Serialize)]
 pub struct PostStepResBody {
-    // None on errors
-    pub step_id: Option<i64>,
+    pub step_id: i64,
 }
 
-async fn post_step(
-    State(db): State<Pool<Sqlite>>,
-    Json(body): Json<PostStepReqBody>,
-) -> impl IntoResponse {
-    let PostStepReqBody {
+/// Checks what the database can't before anything gets written
+pub(crate) fn validate_step(test_case_name: &str, step_name: &str, image: &Blob) -> HttpResult {
+    if test_case_name.trim().is_empty() {
+        return Err(HttpError::validation("test case name must not be empty"));
+    }
+    if step_name.trim().is_empty() {
+        return Err(HttpError::validation("step name must not be empty"));
+    }
+    validate_step_image(&image.data)?;
+    Ok(())
+}
+
+/// Adds what got created to `events`, for the caller to publish once it is stored for good.
+/// Runs in the caller's transaction, so the step lands together with its run, test case
+/// and blob or not at all. Objects written for the screenshot are added to `stored`.
+async fn insert_step(
+    conn: &mut SqliteConnection,
+    storage: &Storage,
+    step: NewStep,
+    events: &mut Vec<IngestEvent>,
+    stored: &mut Vec<String>,
+) -> HttpResult<Step> {
+    let NewStep {
         run_id,
         run_tags,
+        run_metadata,
+        test_case_tags,
         test_case_name,
         step_name,
-        img_base64_url,
+        step_tags,
+        image,
+        capture,
         parent_step_id,
         ignore_areas,
-    } = body;
+        comparison_settings,
+        on_conflict,
+    } = step;
 
-    let run = match insert_and_get_run(&db, &run_id, &run_tags).await {
-        Ok(run) => run,
-        Err(err) => {
-            log::error!("{err}");
-            return Json(PostStepResBody { step_id: None });
-        }
-    };
-    let test_case = match insert_and_get_test_case(&db, run.id, &test_case_name, ignore_areas).await
-    {
-        Ok(test_case) => test_case,
-        Err(err) => {
-            log::error!("{err}");
-            return Json(PostStepResBody { step_id: None });
-        }
-    };
-    let step = match insert_and_get_step(
-        &db,
+    if run_id.trim().is_empty() {
+        return Err(HttpError::validation("run id must not be empty"));
+    }
+    validate_step(&test_case_name, &step_name, &image)?;
+    comparison_settings.validate()?;
+
+    let run_is_new = !run_exists(conn, &run_id).await?;
+    let run = insert_and_get_run(conn, &run_id, &run_tags, run_metadata).await?;
+    if run_is_new {
+        events.push(IngestEvent::RunAdded {
+            run_id: run.id,
+            name: run.name,
+        });
+    }
+
+    let test_case_is_new = !test_case_exists(conn, run.id, &test_case_name).await?;
+    let test_case = insert_and_get_test_case(
+        conn,
+        run.id,
+        &test_case_name,
+        ignore_areas,
+        comparison_settings,
+        &test_case_tags,
+        on_conflict,
+    )
+    .await?;
+    if test_case_is_new {
+        events.push(IngestEvent::TestCaseAdded {
+            run_id: run.id,
+            test_case_id: test_case.id,
+            name: test_case.name,
+        });
+    }
+
+    let step = insert_and_get_step(
+        conn,
+        storage,
         test_case.id,
         &step_name,
-        &img_base64_url,
+        &image,
         parent_step_id,
+        &step_tags,
+        &capture,
+        on_conflict,
+        stored,
     )
-    .await
-    {
-        Ok(step) => step,
-        Err(err) => {
-            log::error!("{err}");
-            return Json(PostStepResBody { step_id: None });
+    .await?;
+    events.push(IngestEvent::StepAdded {
+        run_id: run.id,
+        test_case_id: test_case.id,
+        step_id: step.id,
+        name: step.name.clone(),
+    });
+    Ok(step)
+}
+
+async fn post_step(
+    State(db): State<Pool<Sqlite>>,
+    Extension(events): Extension<Events>,
+    Extension(storage): Extension<Storage>,
+    JsonBody(body): JsonBody<PostStepReqBody>,
+) -> HttpResult<Json<PostStepResBody>> {
+    let step = NewStep::try_from(body)?;
+
+    let mut tx = db.begin().await?;
+    let mut ingested = vec![];
+    let mut stored = vec![];
+    let step = insert_step(&mut tx, &storage, step, &mut ingested, &mut stored).await;
+    let step = storage.commit(tx, &stored, step).await?;
+    events.publish_all(ingested);
+
+    Ok(Json(PostStepResBody { step_id: step.id }))
+}
+
+/// Same as [`post_step`], but takes the screenshot as a binary `image` part of a
+/// `multipart/form-data` body instead of a base64 data URI.
+///
+/// Text parts mirror [`PostStepReqBody`]: `run_id`, `test_case_name`, `step_name`,
+/// `parent_step_id`, `ignore_areas`, `comparison_settings`, `run_metadata` and `capture` (as JSON), `on_conflict` and the repeatable
+/// `run_tags`, `test_case_tags` and `step_tags`.
+async fn post_step_multipart(
+    State(db): State<Pool<Sqlite>>,
+    Extension(events): Extension<Events>,
+    Extension(storage): Extension<Storage>,
+    MultipartBody(mut multipart): MultipartBody,
+) -> HttpResult<Json<PostStepResBody>> {
+    let mut run_id = None;
+    let mut run_tags = vec![];
+    let mut run_metadata = None;
+    let mut test_case_tags = vec![];
+    let mut test_case_name = None;
+    let mut step_name = None;
+    let mut step_tags = vec![];
+    let mut image = None;
+    let mut capture = CaptureMetadata::default();
+    let mut parent_step_id = None;
+    let mut ignore_areas = vec![];
+    let mut comparison_settings = ComparisonSettings::default();
+    let mut on_conflict = ConflictPolicy::default();
+
+    while let Some(mut field) = multipart.next_field().await? {
+        let Some(name) = field.name().map(str::to_string) else {
+            continue;
+        };
+        match name.as_str() {
+            "run_id" => run_id = Some(field.text().await?),
+            "run_tags" => run_tags.push(field.text().await?),
+            "run_metadata" => run_metadata = Some(serde_json::from_str(&field.text().await?)?),
+            "test_case_tags" => test_case_tags.push(field.text().await?),
+            "step_tags" => step_tags.push(field.text().await?),
+            "test_case_name" => test_case_name = Some(field.text().await?),
+            "step_name" => step_name = Some(field.text().await?),
+            "parent_step_id" => parent_step_id = Some(field.text().await?.parse()?),
+            "ignore_areas" => ignore_areas = serde_json::from_str(&field.text().await?)?,
+            "comparison_settings" => {
+                comparison_settings = serde_json::from_str(&field.text().await?)?
+            }
+            "capture" => capture = serde_json::from_str(&field.text().await?)?,
+            "on_conflict" => {
+                on_conflict = field.text().await?.parse().map_err(|_| {
+                    HttpError::validation("`on_conflict` must be reject, overwrite or keep_both")
+                })?
+            }
+            "image" => {
+                let mime = field.content_type().unwrap_or("image/png").to_string();
+                let mut bytes = vec![];
+                while let Some(chunk) = field.chunk().await? {
+                    bytes.extend_from_slice(&chunk);
+                }
+                image = Some(Blob::new(image_mime(&mime, &bytes), bytes));
+            }
+            _ => {}
         }
+    }
+
+    let step = NewStep {
+        run_id: run_id.ok_or_else(|| HttpError::validation("missing `run_id` field"))?,
+        run_tags,
+        run_metadata,
+        test_case_tags,
+        test_case_name: test_case_name
+            .ok_or_else(|| HttpError::validation("missing `test_case_name` field"))?,
+        step_name: step_name.ok_or_else(|| HttpError::validation("missing `step_name` field"))?,
+        step_tags,
+        image: image.ok_or_else(|| HttpError::validation("missing `image` field"))?,
+        capture,
+        parent_step_id,
+        ignore_areas,
+        comparison_settings,
+        on_conflict,
     };
+    let mut tx = db.begin().await?;
+    let mut ingested = vec![];
+    let mut stored = vec![];
+    let step = insert_step(&mut tx, &storage, step, &mut ingested, &mut stored).await;
+    let step = storage.commit(tx, &stored, step).await?;
+    events.publish_all(ingested);
 
-    Json(PostStepResBody {
-        step_id: Some(step.id),
-    })
+    Ok(Json(PostStepResBody { step_id: step.id }))
 }
 
 pub fn router(db: Pool<Sqlite>) -> Router {
@@ -133,5 +389,15 @@ pub fn router(db: Pool<Sqlite>) -> Router {
             get(diff_steps_by_image),
         )
         .route("/steps", post(post_step))
-        .with_state(db)
+        .route(
+            "/steps/multipart",
+            post(post_step_multipart).layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES)),
+        )
+        .with_state(db.clone())
+        .nest("/batch", batch::router(db.clone()))
+        .nest("/comparisons", comparisons::router(db.clone()))
+        .nest("/events", events::router())
+        .nest("/runs", runs::router(db.clone()))
+        .nest("/tags", tags::router(db.clone()))
+        .nest("/test_cases", test_cases::router(db))
 }
@@ -0,0 +1,271 @@
+use async_recursion::async_recursion;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::State;
+use axum::routing::post;
+use axum::Extension;
+use axum::Json;
+use axum::Router;
+use serde::Deserialize;
+use serde::Serialize;
+use sqlx::Pool;
+use sqlx::Sqlite;
+use sqlx::SqliteConnection;
+
+use super::validate_step;
+use crate::db::insert_and_get_run;
+use crate::db::insert_and_get_step;
+use crate::db::insert_and_get_test_case;
+use crate::db::run_exists;
+use crate::db::test_case_exists;
+use crate::error::HttpError;
+use crate::error::HttpResult;
+use crate::error::JsonBody;
+use crate::events::Events;
+use crate::models::blob::Blob;
+use crate::models::capture_metadata::CaptureMetadata;
+use crate::models::comparison_settings::ComparisonSettings;
+use crate::models::conflict_policy::ConflictPolicy;
+use crate::models::ingest_event::IngestEvent;
+use crate::models::run_metadata::RunMetadata;
+use crate::models::test_case::IgnoreArea;
+use crate::services::data_uri_to_bytes;
+use crate::storage::Storage;
+
+/// A batch carries many screenshots, so it gets a larger budget than a single step. It is still
+/// bounded because the whole body is buffered and decoded in memory, and the one write transaction
+/// holding the database stays open while every screenshot is put into storage, blocking all other
+/// uploads. Larger runs are sent as several batches with the same `run_id`.
+const MAX_BATCH_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct PostBatchReqBody {
+    run_id: String,
+    #[serde(default)]
+    run_tags: Vec<String>,
+    #[serde(default)]
+    run_metadata: Option<RunMetadata>,
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+    test_cases: Vec<BatchTestCase>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchTestCase {
+    name: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    ignore_areas: Vec<IgnoreArea>,
+    #[serde(default)]
+    comparison_settings: ComparisonSettings,
+    steps: Vec<BatchStep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchStep {
+    name: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    img_base64_url: String,
+    #[serde(default)]
+    capture: CaptureMetadata,
+    #[serde(default)]
+    steps: Vec<BatchStep>,
+}
+
+#[derive(Debug, Serialize)]
+struct PostBatchResBody {
+    run_id: i64,
+    test_cases: Vec<BatchTestCaseIds>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchTestCaseIds {
+    name: String,
+    test_case_id: i64,
+    steps: Vec<BatchStepIds>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchStepIds {
+    name: String,
+    step_id: i64,
+    steps: Vec<BatchStepIds>,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[async_recursion]
+async fn insert_steps(
+    conn: &mut SqliteConnection,
+    storage: &Storage,
+    run_id: i64,
+    test_case_id: i64,
+    test_case_name: &str,
+    parent_step_id: Option<i64>,
+    on_conflict: ConflictPolicy,
+    steps: Vec<BatchStep>,
+    events: &mut Vec<IngestEvent>,
+    stored: &mut Vec<String>,
+) -> HttpResult<Vec<BatchStepIds>> {
+    let mut ids = vec![];
+    for BatchStep {
+        name,
+        tags,
+        img_base64_url,
+        capture,
+        steps,
+    } in steps
+    {
+        let (mime, data) = data_uri_to_bytes(&img_base64_url)?;
+        let image = Blob::new(mime, data);
+        validate_step(test_case_name, &name, &image)?;
+        let step = insert_and_get_step(
+            conn,
+            storage,
+            test_case_id,
+            &name,
+            &image,
+            parent_step_id,
+            &tags,
+            &capture,
+            on_conflict,
+            stored,
+        )
+        .await?;
+        events.push(IngestEvent::StepAdded {
+            run_id,
+            test_case_id,
+            step_id: step.id,
+            name: step.name,
+        });
+        ids.push(BatchStepIds {
+            name,
+            step_id: step.id,
+            steps: insert_steps(
+                conn,
+                storage,
+                run_id,
+                test_case_id,
+                test_case_name,
+                Some(step.id),
+                on_conflict,
+                steps,
+                events,
+                stored,
+            )
+            .await?,
+        });
+    }
+    Ok(ids)
+}
+
+/// Ingests a whole run in a single transaction, so children no longer wait on their parent's id
+async fn post_batch(
+    State(db): State<Pool<Sqlite>>,
+    Extension(events): Extension<Events>,
+    Extension(storage): Extension<Storage>,
+    JsonBody(body): JsonBody<PostBatchReqBody>,
+) -> HttpResult<Json<PostBatchResBody>> {
+    if body.run_id.trim().is_empty() {
+        return Err(HttpError::validation("run id must not be empty"));
+    }
+
+    let mut tx = db.begin().await?;
+    let mut ingested = vec![];
+    let mut stored = vec![];
+    let ids = insert_batch(&mut tx, &storage, body, &mut ingested, &mut stored).await;
+    let ids = storage.commit(tx, &stored, ids).await?;
+    events.publish_all(ingested);
+
+    Ok(Json(ids))
+}
+
+/// Runs in the caller's transaction, like [`insert_steps`]
+async fn insert_batch(
+    conn: &mut SqliteConnection,
+    storage: &Storage,
+    body: PostBatchReqBody,
+    events: &mut Vec<IngestEvent>,
+    stored: &mut Vec<String>,
+) -> HttpResult<PostBatchResBody> {
+    let PostBatchReqBody {
+        run_id,
+        run_tags,
+        run_metadata,
+        on_conflict,
+        test_cases,
+    } = body;
+
+    let run_is_new = !run_exists(conn, &run_id).await?;
+    let run = insert_and_get_run(conn, &run_id, &run_tags, run_metadata).await?;
+    if run_is_new {
+        events.push(IngestEvent::RunAdded {
+            run_id: run.id,
+            name: run.name.clone(),
+        });
+    }
+    let mut test_case_ids = vec![];
+    for BatchTestCase {
+        name,
+        tags,
+        ignore_areas,
+        comparison_settings,
+        steps,
+    } in test_cases
+    {
+        if name.trim().is_empty() {
+            return Err(HttpError::validation("test case name must not be empty"));
+        }
+        comparison_settings.validate()?;
+        let test_case_is_new = !test_case_exists(conn, run.id, &name).await?;
+        let test_case = insert_and_get_test_case(
+            conn,
+            run.id,
+            &name,
+            ignore_areas,
+            comparison_settings,
+            &tags,
+            on_conflict,
+        )
+        .await?;
+        if test_case_is_new {
+            events.push(IngestEvent::TestCaseAdded {
+                run_id: run.id,
+                test_case_id: test_case.id,
+                name: name.clone(),
+            });
+        }
+        let steps = insert_steps(
+            conn,
+            storage,
+            run.id,
+            test_case.id,
+            &name,
+            None,
+            on_conflict,
+            steps,
+            events,
+            stored,
+        )
+        .await?;
+        test_case_ids.push(BatchTestCaseIds {
+            name,
+            test_case_id: test_case.id,
+            steps,
+        });
+    }
+
+    Ok(PostBatchResBody {
+        run_id: run.id,
+        test_cases: test_case_ids,
+    })
+}
+
+pub fn router(db: Pool<Sqlite>) -> Router {
+    Router::new()
+        .route(
+            "/",
+            post(post_batch).layer(DefaultBodyLimit::max(MAX_BATCH_BYTES)),
+        )
+        .with_state(db)
+}
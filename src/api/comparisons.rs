@@ -0,0 +1,196 @@
+use askama::Template;
+use axum::extract::State;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Extension;
+use axum::Json;
+use axum::Router;
+use serde::Deserialize;
+use sqlx::Pool;
+use sqlx::Sqlite;
+
+use crate::error::HttpResult;
+use crate::error::PathParams;
+use crate::error::QueryParams;
+use crate::models::comparison::RunComparison;
+use crate::models::comparison::StepPair;
+use crate::models::comparison::StepStatus;
+use crate::models::comparison_settings::ComparisonSettings;
+use crate::services::compare_runs;
+use crate::storage::Storage;
+
+#[derive(Template)]
+#[template(path = "api/junit.xml")]
+struct JunitReport {
+    name: String,
+    tests: usize,
+    failures: usize,
+    suites: Vec<JunitSuite>,
+}
+
+/// One per test case
+struct JunitSuite {
+    name: String,
+    failures: usize,
+    cases: Vec<JunitCase>,
+}
+
+/// One per step
+struct JunitCase {
+    name: String,
+    failure: Option<JunitFailure>,
+}
+
+struct JunitFailure {
+    kind: StepStatus,
+    message: String,
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct JunitQueryParams {
+    /// Where the links in failures point to, defaults to the host the report was requested from
+    base_url: Option<String>,
+}
+
+impl JunitCase {
+    fn new(step: &StepPair, base_url: &str) -> Self {
+        let name = step
+            .path
+            .iter()
+            .chain(std::iter::once(&step.name))
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" / ");
+
+        let failure = match (step.status, step.left_step_id, step.right_step_id) {
+            (StepStatus::Changed, Some(left), Some(right)) => Some(JunitFailure {
+                kind: step.status,
+                message: {
+                    let mut message = format!(
+                        "screenshot changed by {:.4}%",
+                        step.diff_percentage.unwrap_or_default()
+                    );
+                    if let Some(ssim) = step.ssim {
+                        message.push_str(&format!(" (SSIM {ssim:.4})"));
+                    }
+                    if let Some(size) = step.size_mismatch {
+                        message.push_str(&format!(", resized from {size}"));
+                    }
+                    message
+                },
+                url: format!("{base_url}/steps/{left}/{right}"),
+            }),
+            (StepStatus::Removed, Some(left), _) => Some(JunitFailure {
+                kind: step.status,
+                message: "step is missing from the right run".to_string(),
+                url: format!("{base_url}/steps/{left}"),
+            }),
+            (StepStatus::Added, _, Some(right)) => Some(JunitFailure {
+                kind: step.status,
+                message: "step is new in the right run".to_string(),
+                url: format!("{base_url}/steps/{right}"),
+            }),
+            _ => None,
+        };
+
+        JunitCase { name, failure }
+    }
+}
+
+impl JunitReport {
+    fn new(comparison: RunComparison, base_url: &str) -> Self {
+        let suites: Vec<JunitSuite> = comparison
+            .test_cases
+            .into_iter()
+            .map(|test_case| {
+                let cases: Vec<JunitCase> = test_case
+                    .steps
+                    .iter()
+                    .map(|step| JunitCase::new(step, base_url))
+                    .collect();
+                JunitSuite {
+                    name: test_case.name,
+                    failures: cases.iter().filter(|case| case.failure.is_some()).count(),
+                    cases,
+                }
+            })
+            .collect();
+
+        JunitReport {
+            name: format!(
+                "runs {} and {}",
+                comparison.left_run_id, comparison.right_run_id
+            ),
+            tests: suites.iter().map(|suite| suite.cases.len()).sum(),
+            failures: suites.iter().map(|suite| suite.failures).sum(),
+            suites,
+        }
+    }
+}
+
+/// What the run comparison page shows, for CI to act on without scraping HTML
+async fn run_comparison(
+    State(db): State<Pool<Sqlite>>,
+    Extension(storage): Extension<Storage>,
+    Extension(settings): Extension<ComparisonSettings>,
+    PathParams((left_run_id, right_run_id)): PathParams<(i64, i64)>,
+) -> HttpResult<Json<RunComparison>> {
+    Ok(Json(
+        compare_runs(
+            &db,
+            &storage,
+            settings,
+            left_run_id,
+            right_run_id,
+            |_, _, _| Ok(()),
+        )
+        .await?,
+    ))
+}
+
+/// The same comparison as a JUnit XML report, for CI systems that render those natively
+async fn run_comparison_junit(
+    State(db): State<Pool<Sqlite>>,
+    Extension(storage): Extension<Storage>,
+    Extension(settings): Extension<ComparisonSettings>,
+    PathParams((left_run_id, right_run_id)): PathParams<(i64, i64)>,
+    QueryParams(JunitQueryParams { base_url }): QueryParams<JunitQueryParams>,
+    headers: HeaderMap,
+) -> HttpResult<impl IntoResponse> {
+    let base_url = match base_url {
+        Some(base_url) => base_url.trim_end_matches('/').to_string(),
+        None => headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(|host| format!("http://{host}"))
+            .unwrap_or_default(),
+    };
+
+    let comparison = compare_runs(
+        &db,
+        &storage,
+        settings,
+        left_run_id,
+        right_run_id,
+        |_, _, _| Ok(()),
+    )
+    .await?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/xml")],
+        JunitReport::new(comparison, &base_url).render()?,
+    ))
+}
+
+pub fn router(db: Pool<Sqlite>) -> Router {
+    Router::new()
+        .route("/:left_run_id/:right_run_id", get(run_comparison))
+        .route(
+            "/:left_run_id/:right_run_id/junit",
+            get(run_comparison_junit),
+        )
+        .with_state(db)
+}
@@ -0,0 +1,208 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::routing::post;
+use axum::routing::put;
+use axum::Extension;
+use axum::Json;
+use axum::Router;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use sqlx::Pool;
+use sqlx::Sqlite;
+
+use crate::db::count_runs;
+use crate::db::delete_run;
+use crate::db::finish_run;
+use crate::db::get_run;
+use crate::db::get_run_test_cases;
+use crate::db::get_runs_page;
+use crate::db::insert_and_get_run;
+use crate::db::run_exists;
+use crate::db::set_run_pinned;
+use crate::error::HttpError;
+use crate::error::HttpResult;
+use crate::error::JsonBody;
+use crate::error::PathParams;
+use crate::error::QueryParams;
+use crate::events::Events;
+use crate::models::ingest_event::IngestEvent;
+use crate::models::run::Run;
+use crate::models::run_metadata::RunMetadata;
+use crate::models::run_status::RunStatus;
+use crate::models::test_case::TestCase;
+use crate::storage::Storage;
+
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+struct PostRunReqBody {
+    run_id: String,
+    #[serde(default)]
+    run_tags: Vec<String>,
+    #[serde(default)]
+    run_metadata: Option<RunMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FinalizeRunReqBody {
+    status: RunStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct RunStatusResBody {
+    run_id: i64,
+    status: RunStatus,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+impl From<Run> for RunStatusResBody {
+    fn from(run: Run) -> Self {
+        RunStatusResBody {
+            run_id: run.id,
+            status: run.status,
+            finished_at: run.finished_at,
+        }
+    }
+}
+
+/// Opens a run up front, so it shows as in progress before its first step lands.
+/// Opening a run that already exists only merges in the given tags and metadata.
+async fn post_run(
+    State(db): State<Pool<Sqlite>>,
+    Extension(events): Extension<Events>,
+    JsonBody(body): JsonBody<PostRunReqBody>,
+) -> HttpResult<Json<RunStatusResBody>> {
+    let PostRunReqBody {
+        run_id,
+        run_tags,
+        run_metadata,
+    } = body;
+
+    if run_id.trim().is_empty() {
+        return Err(HttpError::validation("run id must not be empty"));
+    }
+
+    let mut tx = db.begin().await?;
+    let run_is_new = !run_exists(&mut tx, &run_id).await?;
+    let run = insert_and_get_run(&mut tx, &run_id, &run_tags, run_metadata).await?;
+    tx.commit().await?;
+    if run_is_new {
+        events.publish(IngestEvent::RunAdded {
+            run_id: run.id,
+            name: run.name.clone(),
+        });
+    }
+    Ok(Json(run.into()))
+}
+
+async fn finalize_run(
+    State(db): State<Pool<Sqlite>>,
+    Extension(events): Extension<Events>,
+    PathParams(run_id): PathParams<i64>,
+    JsonBody(body): JsonBody<FinalizeRunReqBody>,
+) -> HttpResult<Json<RunStatusResBody>> {
+    let run = finish_run(&db, run_id, body.status).await?;
+    events.publish(IngestEvent::RunFinalized {
+        run_id: run.id,
+        status: run.status,
+    });
+    Ok(Json(run.into()))
+}
+
+#[derive(Debug, Deserialize)]
+struct ListRunsQueryParams {
+    #[serde(default = "default_limit")]
+    limit: i64,
+    #[serde(default)]
+    offset: i64,
+    /// Only runs carrying this tag
+    tag: Option<String>,
+}
+
+fn default_limit() -> i64 {
+    50
+}
+
+#[derive(Debug, Serialize)]
+struct ListRunsResBody {
+    total: i64,
+    runs: Vec<Run>,
+}
+
+async fn list_runs(
+    State(db): State<Pool<Sqlite>>,
+    QueryParams(query): QueryParams<ListRunsQueryParams>,
+) -> HttpResult<Json<ListRunsResBody>> {
+    if !(1..=MAX_PAGE_SIZE).contains(&query.limit) || query.offset < 0 {
+        return Err(HttpError::validation(format!(
+            "`limit` must be within 1..={MAX_PAGE_SIZE} and `offset` must not be negative"
+        )));
+    }
+
+    let tag = query.tag.as_deref();
+    Ok(Json(ListRunsResBody {
+        total: count_runs(&db, tag).await?,
+        runs: get_runs_page(&db, tag, query.limit, query.offset).await?,
+    }))
+}
+
+async fn run(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(run_id): PathParams<i64>,
+) -> HttpResult<Json<Run>> {
+    Ok(Json(get_run(&db, run_id).await?))
+}
+
+async fn remove_run(
+    State(db): State<Pool<Sqlite>>,
+    Extension(storage): Extension<Storage>,
+    PathParams(run_id): PathParams<i64>,
+) -> HttpResult<StatusCode> {
+    delete_run(&db, &storage, run_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pin_run(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(run_id): PathParams<i64>,
+) -> HttpResult<Json<Run>> {
+    Ok(Json(set_run_pinned(&db, run_id, true).await?))
+}
+
+async fn unpin_run(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(run_id): PathParams<i64>,
+) -> HttpResult<Json<Run>> {
+    Ok(Json(set_run_pinned(&db, run_id, false).await?))
+}
+
+async fn run_test_cases(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(run_id): PathParams<i64>,
+) -> HttpResult<Json<Vec<TestCase>>> {
+    // 404 for unknown runs instead of an empty list
+    get_run(&db, run_id).await?;
+    Ok(Json(get_run_test_cases(&db, run_id).await?))
+}
+
+async fn run_status(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(run_id): PathParams<i64>,
+) -> HttpResult<Json<RunStatusResBody>> {
+    let run = get_run(&db, run_id).await?;
+    Ok(Json(run.into()))
+}
+
+pub fn router(db: Pool<Sqlite>) -> Router {
+    Router::new()
+        .route("/", get(list_runs).post(post_run))
+        .route("/:run_id", get(run).delete(remove_run))
+        .route("/:run_id/test_cases", get(run_test_cases))
+        .route("/:run_id/pin", put(pin_run).delete(unpin_run))
+        .route("/:run_id/finalize", post(finalize_run))
+        .route("/:run_id/status", get(run_status))
+        .with_state(db)
+}
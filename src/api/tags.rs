@@ -0,0 +1,62 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Json;
+use axum::Router;
+use sqlx::Pool;
+use sqlx::Sqlite;
+
+use crate::db::delete_tag_comparison_settings;
+use crate::db::get_tag_comparison_settings;
+use crate::db::set_tag_comparison_settings;
+use crate::error::ErrorKind;
+use crate::error::HttpResult;
+use crate::error::JsonBody;
+use crate::error::PathParams;
+use crate::models::comparison_settings::ComparisonSettings;
+
+/// Applies to every run carrying the tag, unless its test cases set their own
+async fn comparison_settings(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(tag): PathParams<String>,
+) -> HttpResult<Json<ComparisonSettings>> {
+    let settings = get_tag_comparison_settings(&db, &tag)
+        .await?
+        .ok_or_else(|| {
+            ErrorKind::NotFound.error(format!("tag `{tag}` has no comparison settings"))
+        })?;
+    Ok(Json(settings))
+}
+
+async fn put_comparison_settings(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(tag): PathParams<String>,
+    JsonBody(settings): JsonBody<ComparisonSettings>,
+) -> HttpResult<Json<ComparisonSettings>> {
+    settings.validate()?;
+    set_tag_comparison_settings(&db, &tag, &settings).await?;
+    Ok(Json(settings))
+}
+
+async fn remove_comparison_settings(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(tag): PathParams<String>,
+) -> HttpResult<StatusCode> {
+    if !delete_tag_comparison_settings(&db, &tag).await? {
+        return Err(ErrorKind::NotFound
+            .error(format!("tag `{tag}` has no comparison settings"))
+            .into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn router(db: Pool<Sqlite>) -> Router {
+    Router::new()
+        .route(
+            "/:tag/comparison_settings",
+            get(comparison_settings)
+                .put(put_comparison_settings)
+                .delete(remove_comparison_settings),
+        )
+        .with_state(db)
+}
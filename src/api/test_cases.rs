@@ -0,0 +1,84 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Extension;
+use axum::Json;
+use axum::Router;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::Pool;
+use sqlx::Sqlite;
+
+use crate::db::delete_test_case;
+use crate::db::get_steps;
+use crate::db::get_test_case;
+use crate::error::HttpResult;
+use crate::error::PathParams;
+use crate::models::step::Step;
+use crate::models::tag::Tag;
+use crate::models::test_case::TestCase;
+use crate::storage::Storage;
+
+#[derive(Debug, Serialize)]
+struct TestCaseResBody {
+    #[serde(flatten)]
+    test_case: TestCase,
+    steps: Vec<StepNode>,
+}
+
+/// A [`Step`] with a link to its screenshot in place of the image itself
+#[derive(Debug, Serialize)]
+struct StepNode {
+    id: i64,
+    name: String,
+    attempt: i64,
+    created_at: DateTime<Utc>,
+    tags: Vec<Tag>,
+    image_url: String,
+    thumbnail_url: String,
+    steps: Vec<StepNode>,
+}
+
+impl From<Step> for StepNode {
+    fn from(step: Step) -> Self {
+        StepNode {
+            image_url: format!("/images/steps/{}", step.id),
+            thumbnail_url: format!("/images/thumbnails/{}", step.id),
+            id: step.id,
+            name: step.name,
+            attempt: step.attempt,
+            created_at: step.created_at,
+            tags: step.tags,
+            steps: step.children_steps.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+async fn test_case(
+    State(db): State<Pool<Sqlite>>,
+    PathParams(test_case_id): PathParams<i64>,
+) -> HttpResult<Json<TestCaseResBody>> {
+    let test_case = get_test_case(&db, test_case_id).await?;
+    let steps = get_steps(&mut *db.acquire().await?, test_case_id, None).await?;
+
+    Ok(Json(TestCaseResBody {
+        test_case,
+        steps: steps.into_iter().map(Into::into).collect(),
+    }))
+}
+
+async fn remove_test_case(
+    State(db): State<Pool<Sqlite>>,
+    Extension(storage): Extension<Storage>,
+    PathParams(test_case_id): PathParams<i64>,
+) -> HttpResult<StatusCode> {
+    delete_test_case(&db, &storage, test_case_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn router(db: Pool<Sqlite>) -> Router {
+    Router::new()
+        .route("/:test_case_id", get(test_case).delete(remove_test_case))
+        .with_state(db)
+}
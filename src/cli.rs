@@ -0,0 +1,3 @@
+pub mod compare;
+pub mod screenshots;
+pub mod upload;
@@ -0,0 +1,223 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::Context;
+use anyhow::Result;
+use clap::Args;
+use clap::Subcommand;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::SqlitePool;
+
+use super::screenshots::load_test_cases;
+use super::screenshots::LocalImages;
+use crate::models::comparison::RunComparison;
+use crate::models::comparison::StepPair;
+use crate::models::comparison::StepStatus;
+use crate::models::comparison::TestCaseStatus;
+use crate::models::comparison_settings::ComparisonSettings;
+use crate::models::comparison_settings::DiffMethod;
+use crate::services::compare_runs;
+use crate::services::compare_test_cases;
+use crate::services::StepComparison;
+use crate::storage::Storage;
+
+/// Compares two runs or two screenshot directories without a server.
+///
+/// Exits with 0 when nothing changed beyond the threshold, 1 when something did
+/// and 2 when the comparison could not be made.
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    #[command(subcommand)]
+    source: Source,
+    /// Diff percentage a step may change by and still pass, `DIFF_THRESHOLD` by default.
+    /// Test cases and run tags with a threshold of their own keep it.
+    #[arg(long, global = true)]
+    threshold: Option<f64>,
+    /// Difference per color channel, from 0 to 255, that is not a change,
+    /// `DIFF_COLOR_TOLERANCE` by default.
+    /// Test cases and run tags with a tolerance of their own keep it.
+    #[arg(long, global = true)]
+    color_tolerance: Option<u8>,
+    /// How pixels are compared, pixel or perceptual, `DIFF_METHOD` by default.
+    /// Test cases and run tags with a method of their own keep it.
+    #[arg(long, global = true)]
+    method: Option<DiffMethod>,
+    /// SSIM, from 0 to 1, below which a step fails, deciding in place of the threshold.
+    /// `DIFF_MIN_SSIM` by default, test cases and run tags with one of their own keep it.
+    #[arg(long, global = true)]
+    min_ssim: Option<f64>,
+    /// Directory to write the diff PNG of every failing step to
+    #[arg(long, global = true)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Source {
+    /// Compare two runs of a radioguard database
+    Runs {
+        left: i64,
+        right: i64,
+        #[arg(long, env = "DATABASE_URL", default_value = "sqlite:data.db")]
+        database: String,
+    },
+    /// Compare two directories laid out the way `upload` reads them
+    Dirs { left: PathBuf, right: PathBuf },
+}
+
+fn step_label(test_case: &str, step: &StepPair) -> String {
+    std::iter::once(test_case)
+        .chain(step.path.iter().map(String::as_str))
+        .chain(std::iter::once(step.name.as_str()))
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+/// Turns a test case or step name into a single path component, so that names like
+/// `/tmp/x` or `..` can't place diffs outside of `--out`
+fn path_component(name: &str) -> String {
+    let component: String = name
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | ':') || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    match component.as_str() {
+        "" | "." | ".." => format!("_{component}"),
+        _ => component,
+    }
+}
+
+/// Steps only count as changed beyond their threshold, so everything but unchanged fails
+fn fails(step: &StepPair) -> bool {
+    step.status != StepStatus::Unchanged
+}
+
+/// Prints what failed and tells whether the comparison passed
+fn report(comparison: &RunComparison) -> bool {
+    let mut failures = 0;
+    for test_case in &comparison.test_cases {
+        if test_case.status != TestCaseStatus::Matched {
+            println!("{:>9} test case {}", test_case.status, test_case.name);
+        }
+        for step in &test_case.steps {
+            if !fails(step) {
+                continue;
+            }
+            failures += 1;
+            let mut line = format!("{:>9} {}", step.status, step_label(&test_case.name, step));
+            if let Some(diff) = step.diff_percentage {
+                line.push_str(&format!(" by {diff:.4}%"));
+            }
+            if let Some(ssim) = step.ssim {
+                line.push_str(&format!(" (SSIM {ssim:.4})"));
+            }
+            if let Some(size) = step.size_mismatch {
+                line.push_str(&format!(", resized from {size}"));
+            }
+            println!("{line}");
+        }
+    }
+
+    let steps: usize = comparison
+        .test_cases
+        .iter()
+        .map(|test_case| test_case.steps.len())
+        .sum();
+    println!("{failures} of {steps} steps failed");
+
+    failures == 0
+}
+
+/// Runs the comparison, `Ok(false)` when it found changes beyond the threshold
+pub async fn run(args: CompareArgs) -> Result<bool> {
+    let CompareArgs {
+        source,
+        threshold,
+        color_tolerance,
+        method,
+        min_ssim,
+        out,
+    } = args;
+
+    // The server reads its `DIFF_*` and `STORAGE*` settings from `.env`, the verdicts have to match
+    if let Err(err) = dotenvy::dotenv() {
+        if !err.not_found() {
+            return Err(err).context("failed to load `.env`");
+        }
+    }
+
+    let global = ComparisonSettings {
+        color_tolerance,
+        threshold,
+        method,
+        min_ssim,
+    }
+    .or(ComparisonSettings::from_env()?);
+    global.validate()?;
+
+    let write_diff = |test_case: &str, step: &StepPair, comparison: &StepComparison| {
+        let Some(out) = &out else {
+            return Ok(());
+        };
+        if !fails(step) {
+            return Ok(());
+        }
+        let dir = step
+            .path
+            .iter()
+            .fold(out.join(path_component(test_case)), |dir, parent| {
+                dir.join(path_component(parent))
+            });
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create `{}`", dir.display()))?;
+        let path = dir.join(format!("{}.png", path_component(&step.name)));
+        comparison
+            .diff_image
+            .save(&path)
+            .with_context(|| format!("failed to write `{}`", path.display()))?;
+        Ok(())
+    };
+
+    let comparison = match source {
+        Source::Runs {
+            left,
+            right,
+            database,
+        } => {
+            let options = SqliteConnectOptions::from_str(&database)?.read_only(true);
+            let db = SqlitePool::connect_with(options)
+                .await
+                .with_context(|| format!("failed to open `{database}`"))?;
+            let storage = Storage::from_env(db.clone())?;
+            compare_runs(&db, &storage, global, left, right, write_diff).await?
+        }
+        Source::Dirs { left, right } => {
+            let mut images = LocalImages::default();
+            let left = load_test_cases(&left, &mut images)?;
+            let right = load_test_cases(&right, &mut images)?;
+            compare_test_cases(0, 0, left, right, &images, global, write_diff).await?
+        }
+    };
+
+    Ok(report(&comparison))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_component_stays_inside_the_output_directory() {
+        assert_eq!(path_component("login"), "login");
+        assert_eq!(path_component("/tmp/rg/escaped"), "_tmp_rg_escaped");
+        assert_eq!(path_component("..\\..\\x"), ".._.._x");
+        assert_eq!(path_component(".."), "_..");
+        assert_eq!(path_component("."), "_.");
+        assert_eq!(path_component(""), "_");
+        assert_eq!(path_component("C:\\x"), "C__x");
+    }
+}
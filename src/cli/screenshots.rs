@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+
+use crate::models::blob::content_hash;
+use crate::models::comparison_settings::ComparisonSettings;
+use crate::models::step::Step;
+use crate::models::test_case::IgnoreArea;
+use crate::models::test_case::TestCaseWithSteps;
+use crate::storage::ImageSource;
+
+/// Optional file in a test case directory, same JSON as the API's `ignore_areas`
+const IGNORE_AREAS_FILE: &str = "ignore_areas.json";
+/// Optional file in a test case directory, same JSON as the API's `comparison_settings`
+const COMPARISON_SETTINGS_FILE: &str = "comparison_settings.json";
+
+/// Files taken as screenshots, the formats the server decodes
+const EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "webp", "gif", "bmp"];
+
+/// A test case directory, laid out as described on [`read_test_cases`]
+#[derive(Debug)]
+pub struct LocalTestCase {
+    pub name: String,
+    pub ignore_areas: Vec<IgnoreArea>,
+    pub comparison_settings: ComparisonSettings,
+    pub steps: Vec<LocalStep>,
+}
+
+#[derive(Debug)]
+pub struct LocalStep {
+    pub name: String,
+    pub path: PathBuf,
+    pub children: Vec<LocalStep>,
+}
+
+/// Screenshots read from disk, keyed the way the blob store keys them
+#[derive(Debug, Default)]
+pub struct LocalImages(HashMap<String, Vec<u8>>);
+
+impl ImageSource for LocalImages {
+    async fn image(&self, hash: &str) -> Result<Vec<u8>> {
+        self.0
+            .get(hash)
+            .cloned()
+            .with_context(|| format!("image {hash} was not read"))
+    }
+}
+
+impl LocalStep {
+    pub fn count(&self) -> usize {
+        1 + self.children.iter().map(LocalStep::count).sum::<usize>()
+    }
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read `{}`", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn is_screenshot(path: &Path) -> bool {
+    path.is_file()
+        && path.extension().is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+fn file_stem(path: &Path) -> Result<String> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(str::to_string)
+        .with_context(|| format!("`{}` is not a valid name", path.display()))
+}
+
+fn read_steps(dir: &Path) -> Result<Vec<LocalStep>> {
+    let entries = sorted_entries(dir)?;
+
+    for entry in entries.iter().filter(|entry| entry.is_dir()) {
+        let has_screenshot = entries
+            .iter()
+            .any(|sibling| is_screenshot(sibling) && sibling.with_extension("") == *entry);
+        if !has_screenshot {
+            bail!(
+                "`{}` has no `{}.png` or other screenshot next to it to nest its screenshots under",
+                entry.display(),
+                file_stem(entry)?
+            );
+        }
+    }
+
+    entries
+        .iter()
+        .filter(|entry| is_screenshot(entry))
+        .map(|path| {
+            let children_dir = path.with_extension("");
+            Ok(LocalStep {
+                name: file_stem(path)?,
+                path: path.clone(),
+                children: if children_dir.is_dir() {
+                    read_steps(&children_dir)?
+                } else {
+                    vec![]
+                },
+            })
+        })
+        .collect()
+}
+
+/// The default when the test case directory has no such file
+fn read_json_file<T: DeserializeOwned + Default>(dir: &Path, file: &str) -> Result<T> {
+    let path = dir.join(file);
+    if !path.is_file() {
+        return Ok(T::default());
+    }
+    let json = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("invalid `{}`", path.display()))
+}
+
+/// Every subdirectory of `dir` is a test case and every screenshot in it is a step named after the file.
+/// Screenshots in a directory named like a step (`login/` next to `login.png`) become its child steps.
+pub fn read_test_cases(dir: &Path) -> Result<Vec<LocalTestCase>> {
+    let mut test_cases = vec![];
+    for entry in sorted_entries(dir)? {
+        if is_screenshot(&entry) {
+            bail!("`{}` is not inside a test case directory", entry.display());
+        }
+        if !entry.is_dir() {
+            continue;
+        }
+        let steps = read_steps(&entry)?;
+        if steps.is_empty() {
+            continue;
+        }
+        test_cases.push(LocalTestCase {
+            name: file_stem(&entry)?,
+            ignore_areas: read_json_file(&entry, IGNORE_AREAS_FILE)?,
+            comparison_settings: read_json_file(&entry, COMPARISON_SETTINGS_FILE)?,
+            steps,
+        });
+    }
+    Ok(test_cases)
+}
+
+fn load_steps(
+    steps: &[LocalStep],
+    test_case_id: i64,
+    next_id: &mut i64,
+    images: &mut LocalImages,
+) -> Result<Vec<Step>> {
+    steps
+        .iter()
+        .map(|step| {
+            let bytes = std::fs::read(&step.path)
+                .with_context(|| format!("failed to read `{}`", step.path.display()))?;
+            let image_hash = content_hash(&bytes);
+            images.0.insert(image_hash.clone(), bytes);
+            *next_id += 1;
+            Ok(Step {
+                id: *next_id,
+                name: step.name.clone(),
+                image_hash,
+                attempt: 1,
+                created_at: Utc::now(),
+                test_case_id,
+                tags: vec![],
+                children_steps: load_steps(&step.children, test_case_id, next_id, images)?,
+            })
+        })
+        .collect()
+}
+
+/// Reads the screenshots into the shapes the server compares, ids only count up within `dir`
+pub fn load_test_cases(dir: &Path, images: &mut LocalImages) -> Result<Vec<TestCaseWithSteps>> {
+    let mut next_id = 0;
+    read_test_cases(dir)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, test_case)| {
+            let id = index as i64 + 1;
+            Ok(TestCaseWithSteps {
+                id,
+                run_id: 0,
+                steps: load_steps(&test_case.steps, id, &mut next_id, images)?,
+                name: test_case.name,
+                ignore_areas: test_case.ignore_areas,
+                comparison_settings: test_case.comparison_settings,
+                created_at: Utc::now(),
+                tags: vec![],
+            })
+        })
+        .collect()
+}
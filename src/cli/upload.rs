@@ -0,0 +1,336 @@
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use clap::Args;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use reqwest::multipart::Form;
+use reqwest::multipart::Part;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Semaphore;
+
+use super::screenshots::read_test_cases;
+use super::screenshots::LocalStep;
+use super::screenshots::LocalTestCase;
+use crate::models::conflict_policy::ConflictPolicy;
+use crate::services::image_mime;
+
+/// Uploads a directory of PNG, JPEG, WebP, GIF or BMP screenshots as a run.
+///
+/// Every subdirectory of `dir` is a test case and every screenshot in it is a step named after the file.
+/// Screenshots in a directory named like a step (`login/` next to `login.png`) become its child steps.
+/// An `ignore_areas.json` in a test case directory sets the test case's ignore areas,
+/// a `comparison_settings.json` how its steps are compared.
+#[derive(Debug, Args)]
+pub struct UploadArgs {
+    /// Directory holding one subdirectory per test case
+    dir: PathBuf,
+    /// Name of the run, uploading to an existing run adds to it
+    #[arg(long)]
+    run: String,
+    /// Tag for the run, can be repeated
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// Radioguard server to upload to
+    #[arg(
+        long,
+        env = "RADIOGUARD_SERVER",
+        default_value = "http://127.0.0.1:3000"
+    )]
+    server: String,
+    /// How many screenshots are uploaded at the same time
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+    /// What to do with steps that already exist with a different screenshot
+    #[arg(long, default_value_t = ConflictPolicy::Reject)]
+    on_conflict: ConflictPolicy,
+    /// Run to compare against, defaults to the previous run carrying the first tag
+    #[arg(long)]
+    baseline: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResBody {
+    error: ErrorResBodyInner,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResBodyInner {
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunResBody {
+    run_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct StepResBody {
+    step_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListRunsResBody {
+    total: i64,
+    runs: Vec<ListedRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListedRun {
+    id: i64,
+}
+
+/// Turns error responses into errors carrying the server's message
+async fn check(res: reqwest::Response) -> Result<reqwest::Response> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    match res.json::<ErrorResBody>().await {
+        Ok(ErrorResBody { error }) => bail!("{status} {}: {}", error.code, error.message),
+        Err(_) => bail!("{status}"),
+    }
+}
+
+struct Uploader {
+    client: reqwest::Client,
+    server: String,
+    run: String,
+    tags: Vec<String>,
+    on_conflict: ConflictPolicy,
+    concurrency: usize,
+    permits: Semaphore,
+}
+
+impl Uploader {
+    async fn open_run(&self) -> Result<i64> {
+        let res = self
+            .client
+            .post(format!("{}/api/runs", self.server))
+            .json(&json!({ "run_id": self.run, "run_tags": self.tags }))
+            .send()
+            .await?;
+        Ok(check(res).await?.json::<RunResBody>().await?.run_id)
+    }
+
+    async fn finalize_run(&self, run_id: i64, status: &str) -> Result<()> {
+        let res = self
+            .client
+            .post(format!("{}/api/runs/{run_id}/finalize", self.server))
+            .json(&json!({ "status": status }))
+            .send()
+            .await?;
+        check(res).await?;
+        Ok(())
+    }
+
+    async fn upload_step(
+        &self,
+        test_case: &LocalTestCase,
+        step: &LocalStep,
+        parent_step_id: Option<i64>,
+    ) -> Result<i64> {
+        let _permit = self.permits.acquire().await?;
+
+        let bytes = tokio::fs::read(&step.path)
+            .await
+            .with_context(|| format!("failed to read `{}`", step.path.display()))?;
+
+        let mut form = Form::new()
+            .text("run_id", self.run.clone())
+            .text("test_case_name", test_case.name.clone())
+            .text("step_name", step.name.clone())
+            .text("on_conflict", self.on_conflict.to_string())
+            .text(
+                "ignore_areas",
+                serde_json::to_string(&test_case.ignore_areas)?,
+            )
+            .text(
+                "comparison_settings",
+                serde_json::to_string(&test_case.comparison_settings)?,
+            );
+        for tag in &self.tags {
+            form = form.text("run_tags", tag.clone());
+        }
+        if let Some(parent_step_id) = parent_step_id {
+            form = form.text("parent_step_id", parent_step_id.to_string());
+        }
+        let mime = image_mime("", &bytes);
+        let file_name = step
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        form = form.part(
+            "image",
+            Part::bytes(bytes).file_name(file_name).mime_str(&mime)?,
+        );
+
+        let res = self
+            .client
+            .post(format!("{}/api/steps/multipart", self.server))
+            .multipart(form)
+            .send()
+            .await?;
+        let step_id = check(res)
+            .await
+            .with_context(|| format!("failed to upload `{}`", step.path.display()))?
+            .json::<StepResBody>()
+            .await?
+            .step_id;
+
+        println!("{} -> step {step_id}", step.path.display());
+        Ok(step_id)
+    }
+
+    /// Children need their parent's id, so the tree goes up one level at a time
+    async fn upload_test_case(&self, test_case: &LocalTestCase) -> Result<()> {
+        let Some((first, rest)) = test_case.steps.split_first() else {
+            return Ok(());
+        };
+
+        // The first upload creates the test case, racing it would make the others conflict
+        let first_id = self.upload_step(test_case, first, None).await?;
+
+        let mut level: Vec<(Option<i64>, &LocalStep)> = rest
+            .iter()
+            .map(|step| (None, step))
+            .chain(first.children.iter().map(|child| (Some(first_id), child)))
+            .collect();
+
+        while !level.is_empty() {
+            let uploaded: Vec<(i64, &LocalStep)> = futures::stream::iter(level)
+                .map(|(parent_step_id, step)| async move {
+                    let step_id = self.upload_step(test_case, step, parent_step_id).await?;
+                    Ok::<_, anyhow::Error>((step_id, step))
+                })
+                .buffer_unordered(self.concurrency)
+                .try_collect()
+                .await?;
+
+            level = uploaded
+                .into_iter()
+                .flat_map(|(step_id, step)| {
+                    step.children
+                        .iter()
+                        .map(move |child| (Some(step_id), child))
+                })
+                .collect();
+        }
+        Ok(())
+    }
+
+    async fn upload(&self, test_cases: &[LocalTestCase]) -> Result<()> {
+        futures::stream::iter(test_cases)
+            .map(|test_case| self.upload_test_case(test_case))
+            .buffer_unordered(self.concurrency)
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(())
+    }
+
+    /// The newest run before `run_id` that carries the first tag
+    async fn previous_run(&self, run_id: i64) -> Result<Option<i64>> {
+        let Some(tag) = self.tags.first() else {
+            return Ok(None);
+        };
+
+        let url = format!("{}/api/runs", self.server);
+        let res = self
+            .client
+            .get(&url)
+            .query(&[("tag", tag.as_str()), ("limit", "1")])
+            .send()
+            .await?;
+        let total = check(res).await?.json::<ListRunsResBody>().await?.total;
+
+        // Runs are listed oldest first, so the one before last is the previous run
+        if total < 2 {
+            return Ok(None);
+        }
+        let res = self
+            .client
+            .get(&url)
+            .query(&[("tag", tag.as_str()), ("limit", "2")])
+            .query(&[("offset", total - 2)])
+            .send()
+            .await?;
+        let runs = check(res).await?.json::<ListRunsResBody>().await?.runs;
+        Ok(runs
+            .into_iter()
+            .map(|run| run.id)
+            .filter(|id| *id != run_id)
+            .max())
+    }
+}
+
+pub async fn run(args: UploadArgs) -> Result<()> {
+    let UploadArgs {
+        dir,
+        run,
+        tags,
+        server,
+        concurrency,
+        on_conflict,
+        baseline,
+    } = args;
+
+    if concurrency == 0 {
+        bail!("`--concurrency` must be at least 1");
+    }
+
+    let test_cases = read_test_cases(&dir)?;
+    if test_cases.is_empty() {
+        bail!(
+            "`{}` holds no test case directories with screenshots",
+            dir.display()
+        );
+    }
+
+    let uploader = Uploader {
+        client: reqwest::Client::new(),
+        server: server.trim_end_matches('/').to_string(),
+        run,
+        tags,
+        on_conflict,
+        concurrency,
+        permits: Semaphore::new(concurrency),
+    };
+
+    let run_id = uploader
+        .open_run()
+        .await
+        .with_context(|| format!("failed to open run `{}`", uploader.run))?;
+
+    if let Err(err) = uploader.upload(&test_cases).await {
+        uploader.finalize_run(run_id, "failed").await.ok();
+        return Err(err);
+    }
+    uploader.finalize_run(run_id, "completed").await?;
+
+    let steps: usize = test_cases
+        .iter()
+        .flat_map(|test_case| &test_case.steps)
+        .map(LocalStep::count)
+        .sum();
+    println!(
+        "uploaded {steps} screenshots in {} test cases",
+        test_cases.len()
+    );
+    println!("run id: {run_id}");
+
+    let baseline = match baseline {
+        Some(baseline) => Some(baseline),
+        None => uploader.previous_run(run_id).await?,
+    };
+    match baseline {
+        Some(baseline) => println!("comparison: {}/runs/{baseline}/{run_id}", uploader.server),
+        None => println!("no earlier run to compare against, pass `--baseline` to pick one"),
+    }
+
+    Ok(())
+}
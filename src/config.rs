@@ -0,0 +1,21 @@
+use std::env;
+
+use anyhow::Context;
+use anyhow::Result;
+
+/// Parses the environment variable `key`, `None` when it is not set
+pub fn env_var<T>(key: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(key) {
+        Ok(value) => Ok(Some(
+            value
+                .parse()
+                .with_context(|| format!("invalid `{key}`: {value}"))?,
+        )),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("invalid `{key}`")),
+    }
+}
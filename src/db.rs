@@ -1,71 +1,344 @@
 use async_recursion::async_recursion;
 use chrono::Utc;
+use sqlx::Executor;
 use sqlx::Pool;
 use sqlx::Sqlite;
+use sqlx::SqliteConnection;
 
+use crate::error::ErrorKind;
+use crate::models::blob::Blob;
+use crate::models::capture_metadata::CaptureMetadata;
+use crate::models::capture_metadata::StepCapture;
+use crate::models::comparison_settings::ComparisonSettings;
+use crate::models::conflict_policy::ConflictPolicy;
 use crate::models::run::Run;
+use crate::models::run_metadata::RunMetadata;
+use crate::models::run_status::RunStatus;
 use crate::models::step::Step;
 use crate::models::tag::Tag;
+use crate::models::test_case::IgnoreArea;
 use crate::models::test_case::TestCase;
 use crate::models::test_case::TestCaseWithSteps;
+use crate::services::data_uri_to_bytes;
+use crate::storage::Storage;
+use anyhow::Context;
 use anyhow::Result;
 
-pub async fn get_step_data_uri_and_test_case_id(
+pub async fn get_step_image_hash_and_test_case_id(
     id: i64,
     db: &Pool<Sqlite>,
 ) -> Result<(String, i64)> {
+    sqlx::query!(
+        r#"
+    SELECT image_hash AS "image_hash!", test_case_id
+    FROM step
+    WHERE id is $1
+            "#,
+        id
+    )
+    .map(|row| (row.image_hash, row.test_case_id))
+    .fetch_one(db)
+    .await
+    .with_context(|| format!("step {id} not found"))
+}
+
+pub async fn get_blob(executor: impl Executor<'_, Database = Sqlite>, hash: &str) -> Result<Blob> {
+    sqlx::query_as!(
+        Blob,
+        "
+    SELECT hash, mime, data
+    FROM blob
+    WHERE hash = $1
+            ",
+        hash
+    )
+    .fetch_one(executor)
+    .await
+    .with_context(|| format!("image {hash} not found"))
+}
+
+pub async fn blob_exists(conn: &mut SqliteConnection, hash: &str) -> Result<bool> {
     Ok(sqlx::query!(
         "
-    SELECT data_uri, test_case_id
+    SELECT hash
+    FROM blob
+    WHERE hash = $1
+            ",
+        hash
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .is_some())
+}
+
+/// Indexes the image unless an identical one already is, `data` is empty when another backend holds it
+pub async fn insert_blob(
+    conn: &mut SqliteConnection,
+    hash: &str,
+    mime: &str,
+    data: &[u8],
+    dimensions: Option<(u32, u32)>,
+) -> Result<()> {
+    let now = Utc::now().to_string();
+    let width = dimensions.map(|(width, _)| width);
+    let height = dimensions.map(|(_, height)| height);
+    sqlx::query!(
+        "
+    INSERT OR IGNORE INTO blob(hash,mime,data,created_at,width,height)
+    VALUES ($1, $2, $3, $4, $5, $6);
+            ",
+        hash,
+        mime,
+        data,
+        now,
+        width,
+        height
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Records the pixel size of an image stored before sizes were
+pub async fn set_blob_dimensions(
+    db: &Pool<Sqlite>,
+    hash: &str,
+    (width, height): (u32, u32),
+) -> Result<()> {
+    sqlx::query!(
+        "
+    UPDATE blob
+    SET width = $1, height = $2
+    WHERE hash = $3
+            ",
+        width,
+        height,
+        hash
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// The capture metadata of a step with the size of its image, unknown for images stored before sizes were
+pub async fn get_step_capture(db: &Pool<Sqlite>, step_id: i64) -> Result<StepCapture> {
+    let row = sqlx::query!(
+        "
+    SELECT step.capture, blob.width, blob.height
     FROM step
-    WHERE id is $1
+    LEFT JOIN blob ON blob.hash = step.image_hash
+    WHERE step.id = $1
             ",
-        id
+        step_id
     )
-    .map(|row| (row.data_uri, row.test_case_id))
     .fetch_one(db)
+    .await
+    .with_context(|| format!("step {step_id} not found"))?;
+
+    Ok(StepCapture {
+        width: row.width.map(u32::try_from).transpose()?,
+        height: row.height.map(u32::try_from).transpose()?,
+        metadata: serde_json::from_str(&row.capture)?,
+    })
+}
+
+pub async fn get_thumbnail(db: &Pool<Sqlite>, image_hash: &str) -> Result<Option<Vec<u8>>> {
+    Ok(sqlx::query!(
+        "
+    SELECT data
+    FROM thumbnail
+    WHERE image_hash = $1
+            ",
+        image_hash
+    )
+    .map(|row| row.data)
+    .fetch_optional(db)
     .await?)
 }
 
+/// Caches the thumbnail of an image, it goes away together with the image
+pub async fn insert_thumbnail(db: &Pool<Sqlite>, image_hash: &str, data: &[u8]) -> Result<()> {
+    let now = Utc::now().to_string();
+    sqlx::query!(
+        "
+    INSERT OR IGNORE INTO thumbnail(image_hash,data,created_at)
+    VALUES ($1, $2, $3);
+            ",
+        image_hash,
+        data,
+        now
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Drops images no step or earlier attempt shows anymore, returns their hashes
+/// so that the storage can remove them once the transaction went through
+async fn delete_orphaned_blobs(conn: &mut SqliteConnection) -> Result<Vec<String>> {
+    Ok(sqlx::query!(
+        "
+    DELETE FROM blob
+    WHERE hash NOT IN (SELECT image_hash FROM step WHERE image_hash IS NOT NULL)
+    AND hash NOT IN (SELECT image_hash FROM step_attempt WHERE image_hash IS NOT NULL)
+    RETURNING hash
+            "
+    )
+    .map(|row| row.hash)
+    .fetch_all(&mut *conn)
+    .await?)
+}
+
+/// Moves screenshots that older versions inlined as data URIs into the blob store,
+/// a batch per transaction so that a large database doesn't have to fit in memory.
+///
+/// Returns how many were moved and how many could not be decoded. Those are logged and keep
+/// no image, which only breaks the comparisons they are part of rather than the whole server.
+pub async fn migrate_inline_images(db: &Pool<Sqlite>, storage: &Storage) -> Result<(u64, u64)> {
+    const BATCH: i64 = 100;
+    let mut migrated = 0;
+    let mut unreadable = 0;
+    // Unreadable rows stay without an image hash, so batches continue after the last id seen
+    let mut last_step_id = 0;
+    let mut last_attempt_id = 0;
+
+    loop {
+        let mut tx = db.begin().await?;
+        // A batch that fails is retried on the next start and writes the very same objects,
+        // so there is nothing to clean up after it
+        let mut stored = vec![];
+
+        let steps = sqlx::query!(
+            "
+    SELECT id, data_uri
+    FROM step
+    WHERE image_hash IS NULL AND id > $1
+    ORDER BY id
+    LIMIT $2
+            ",
+            last_step_id,
+            BATCH
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let attempts = sqlx::query!(
+            "
+    SELECT id, data_uri
+    FROM step_attempt
+    WHERE image_hash IS NULL AND id > $1
+    ORDER BY id
+    LIMIT $2
+            ",
+            last_attempt_id,
+            BATCH
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if steps.is_empty() && attempts.is_empty() {
+            return Ok((migrated, unreadable));
+        }
+
+        for step in steps {
+            last_step_id = step.id;
+            let (mime, data) = match data_uri_to_bytes(&step.data_uri) {
+                Ok(decoded) => decoded,
+                Err(err) => {
+                    log::warn!("step {} has an unreadable screenshot: {err}", step.id);
+                    unreadable += 1;
+                    continue;
+                }
+            };
+            let blob = Blob::new(mime, data);
+            storage.put(&mut tx, &blob, &mut stored).await?;
+            sqlx::query!(
+                "
+    UPDATE step
+    SET image_hash = $1, data_uri = ''
+    WHERE id = $2
+                ",
+                blob.hash,
+                step.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            migrated += 1;
+        }
+        for attempt in attempts {
+            last_attempt_id = attempt.id;
+            let (mime, data) = match data_uri_to_bytes(&attempt.data_uri) {
+                Ok(decoded) => decoded,
+                Err(err) => {
+                    log::warn!(
+                        "step attempt {} has an unreadable screenshot: {err}",
+                        attempt.id
+                    );
+                    unreadable += 1;
+                    continue;
+                }
+            };
+            let blob = Blob::new(mime, data);
+            storage.put(&mut tx, &blob, &mut stored).await?;
+            sqlx::query!(
+                "
+    UPDATE step_attempt
+    SET image_hash = $1, data_uri = ''
+    WHERE id = $2
+                ",
+                blob.hash,
+                attempt.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            migrated += 1;
+        }
+
+        tx.commit().await?;
+    }
+}
+
 #[async_recursion]
 pub async fn get_steps(
-    db: &Pool<Sqlite>,
+    conn: &mut SqliteConnection,
     left_test_case: i64,
     parent_step_id: Option<i64>,
 ) -> Result<Vec<Step>> {
     let mut steps = sqlx::query!(
-        "
-    SELECT *
+        r#"
+    SELECT id, name, image_hash AS "image_hash!", attempt, created_at, test_case_id
     FROM step
     WHERE step.test_case_id is $1 and step.parent_step_id is $2
-            ",
+            "#,
         left_test_case,
         parent_step_id
     )
-    .fetch_all(db)
+    .fetch_all(&mut *conn)
     .await?
     .into_iter()
     .map(|row| {
         Ok(Step {
             id: row.id,
             name: row.name,
-            data_uri: row.data_uri,
+            image_hash: row.image_hash,
+            attempt: row.attempt,
             created_at: row.created_at.parse()?,
             test_case_id: row.test_case_id,
+            tags: vec![],
             children_steps: vec![],
         })
     })
     .collect::<Result<Vec<_>>>()?;
 
     for step in steps.iter_mut() {
-        step.children_steps = get_steps(db, left_test_case, step.id.into()).await?;
+        step.tags = get_step_tags(&mut *conn, step.id).await?;
+        step.children_steps = get_steps(conn, left_test_case, step.id.into()).await?;
     }
 
     Ok(steps)
 }
 
 pub async fn get_run_test_cases(db: &Pool<Sqlite>, run_id: i64) -> Result<Vec<TestCase>> {
-    let vec = sqlx::query!(
+    let mut vec = sqlx::query!(
         "
     SELECT *
     FROM test_case
@@ -82,10 +355,16 @@ pub async fn get_run_test_cases(db: &Pool<Sqlite>, run_id: i64) -> Result<Vec<Te
             run_id: row.run_id,
             name: row.name,
             ignore_areas: serde_json::from_str(row.ignore_areas.as_str())?,
+            comparison_settings: serde_json::from_str(row.comparison_settings.as_str())?,
             created_at: row.created_at.parse()?,
+            tags: vec![],
         })
     })
     .collect::<Result<Vec<_>>>()?;
+
+    for test_case in vec.iter_mut() {
+        test_case.tags = get_test_case_tags(db, test_case.id).await?;
+    }
     Ok(vec)
 }
 
@@ -99,13 +378,16 @@ pub async fn get_test_case(db: &Pool<Sqlite>, test_case_id: i64) -> Result<TestC
         test_case_id
     )
     .fetch_one(db)
-    .await?;
+    .await
+    .with_context(|| format!("test case {test_case_id} not found"))?;
     Ok(TestCase {
         id: row.id,
         run_id: row.run_id,
         name: row.name,
         ignore_areas: serde_json::from_str(row.ignore_areas.as_str())?,
+        comparison_settings: serde_json::from_str(row.comparison_settings.as_str())?,
         created_at: row.created_at.parse()?,
+        tags: get_test_case_tags(db, test_case_id).await?,
     })
 }
 
@@ -113,7 +395,7 @@ pub async fn get_case_with_steps(
     db: &Pool<Sqlite>,
     test_case_id: i64,
 ) -> Result<TestCaseWithSteps> {
-    let steps = get_steps(db, test_case_id, None).await?;
+    let steps = get_steps(&mut *db.acquire().await?, test_case_id, None).await?;
 
     let row = sqlx::query!(
         "
@@ -130,55 +412,380 @@ pub async fn get_case_with_steps(
         id: row.id,
         run_id: row.run_id,
         name: row.name,
+        ignore_areas: serde_json::from_str(row.ignore_areas.as_str())?,
+        comparison_settings: serde_json::from_str(row.comparison_settings.as_str())?,
         created_at: row.created_at.parse()?,
+        tags: get_test_case_tags(db, test_case_id).await?,
         steps,
     })
 }
 
+pub async fn get_run_tags(db: &Pool<Sqlite>, run_id: i64) -> Result<Vec<Tag>> {
+    Ok(sqlx::query!(
+        "
+    SELECT tag.*
+    FROM tag
+    JOIN run_tag ON run_tag.tag_id = tag.id
+    WHERE run_id = ?;
+            ",
+        run_id,
+    )
+    .map(|row| Tag {
+        id: row.id,
+        value: row.value,
+    })
+    .fetch_all(db)
+    .await?)
+}
+
+/// Settings of those of the run's tags that have any
+pub async fn get_run_tag_comparison_settings(
+    db: &Pool<Sqlite>,
+    run_id: i64,
+) -> Result<Vec<ComparisonSettings>> {
+    sqlx::query!(
+        "
+    SELECT tag_comparison_settings.settings
+    FROM tag_comparison_settings
+    JOIN run_tag ON run_tag.tag_id = tag_comparison_settings.tag_id
+    WHERE run_tag.run_id = ?;
+            ",
+        run_id,
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| Ok(serde_json::from_str(&row.settings)?))
+    .collect()
+}
+
+pub async fn get_tag_comparison_settings(
+    db: &Pool<Sqlite>,
+    tag: &str,
+) -> Result<Option<ComparisonSettings>> {
+    sqlx::query!(
+        "
+    SELECT tag_comparison_settings.settings
+    FROM tag_comparison_settings
+    JOIN tag ON tag.id = tag_comparison_settings.tag_id
+    WHERE tag.value = ?;
+            ",
+        tag,
+    )
+    .fetch_optional(db)
+    .await?
+    .map(|row| Ok(serde_json::from_str(&row.settings)?))
+    .transpose()
+}
+
+/// Creates the tag when no run carries it yet, so it can be configured ahead of the first upload
+pub async fn set_tag_comparison_settings(
+    db: &Pool<Sqlite>,
+    tag: &str,
+    settings: &ComparisonSettings,
+) -> Result<()> {
+    let mut tx = db.begin().await?;
+    let tag = insert_and_get_tag(&mut tx, tag).await?;
+    let settings = serde_json::to_string(settings)?;
+    sqlx::query!(
+        "
+    INSERT INTO tag_comparison_settings(tag_id, settings)
+    VALUES (?, ?)
+    ON CONFLICT(tag_id) DO UPDATE SET settings = excluded.settings;
+            ",
+        tag.id,
+        settings,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Whether the tag had settings to remove
+pub async fn delete_tag_comparison_settings(db: &Pool<Sqlite>, tag: &str) -> Result<bool> {
+    let result = sqlx::query!(
+        "
+    DELETE FROM tag_comparison_settings
+    WHERE tag_id = (SELECT id FROM tag WHERE value = ?);
+            ",
+        tag,
+    )
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_test_case_tags(
+    executor: impl Executor<'_, Database = Sqlite>,
+    test_case_id: i64,
+) -> Result<Vec<Tag>> {
+    Ok(sqlx::query!(
+        "
+    SELECT tag.*
+    FROM tag
+    JOIN test_case_tag ON test_case_tag.tag_id = tag.id
+    WHERE test_case_id = ?;
+            ",
+        test_case_id,
+    )
+    .map(|row| Tag {
+        id: row.id,
+        value: row.value,
+    })
+    .fetch_all(executor)
+    .await?)
+}
+
+pub async fn get_step_tags(
+    executor: impl Executor<'_, Database = Sqlite>,
+    step_id: i64,
+) -> Result<Vec<Tag>> {
+    Ok(sqlx::query!(
+        "
+    SELECT tag.*
+    FROM tag
+    JOIN step_tag ON step_tag.tag_id = tag.id
+    WHERE step_id = ?;
+            ",
+        step_id,
+    )
+    .map(|row| Tag {
+        id: row.id,
+        value: row.value,
+    })
+    .fetch_all(executor)
+    .await?)
+}
+
+/// Whether the test case itself or any of its steps carries the tag
+pub async fn test_case_has_tag(db: &Pool<Sqlite>, test_case_id: i64, tag: &str) -> Result<bool> {
+    Ok(sqlx::query!(
+        r#"
+    SELECT EXISTS(
+        SELECT 1
+        FROM test_case_tag
+        JOIN tag ON tag.id = test_case_tag.tag_id
+        WHERE test_case_id = $1 AND tag.value = $2
+        UNION ALL
+        SELECT 1
+        FROM step_tag
+        JOIN tag ON tag.id = step_tag.tag_id
+        JOIN step ON step.id = step_tag.step_id
+        WHERE step.test_case_id = $1 AND tag.value = $2
+    ) AS "has_tag!: bool"
+            "#,
+        test_case_id,
+        tag
+    )
+    .fetch_one(db)
+    .await?
+    .has_tag)
+}
+
+pub async fn run_exists(conn: &mut SqliteConnection, name: &str) -> Result<bool> {
+    Ok(sqlx::query!(
+        r#"
+    SELECT EXISTS(
+        SELECT 1
+        FROM run
+        WHERE name = $1
+    ) AS "exists!: bool"
+            "#,
+        name
+    )
+    .fetch_one(conn)
+    .await?
+    .exists)
+}
+
+pub async fn test_case_exists(
+    conn: &mut SqliteConnection,
+    run_id: i64,
+    name: &str,
+) -> Result<bool> {
+    Ok(sqlx::query!(
+        r#"
+    SELECT EXISTS(
+        SELECT 1
+        FROM test_case
+        WHERE run_id = $1 AND name = $2
+    ) AS "exists!: bool"
+            "#,
+        run_id,
+        name
+    )
+    .fetch_one(conn)
+    .await?
+    .exists)
+}
+
 pub async fn get_runs(db: Pool<Sqlite>) -> Result<Vec<Run>> {
+    get_runs_page(&db, None, -1, 0).await
+}
+
+/// Runs in creation order, optionally only those tagged with `tag`.
+/// A negative `limit` means no limit.
+pub async fn get_runs_page(
+    db: &Pool<Sqlite>,
+    tag: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Run>> {
     let mut runs = vec![];
 
     let runs_untagged = sqlx::query!(
         "
     SELECT *
     FROM run
+    WHERE $1 IS NULL OR EXISTS(
+        SELECT 1
+        FROM run_tag
+        JOIN tag ON tag.id = run_tag.tag_id
+        WHERE run_tag.run_id = run.id AND tag.value = $1
+    )
+    ORDER BY id
+    LIMIT $2 OFFSET $3
             ",
+        tag,
+        limit,
+        offset
     )
-    .fetch_all(&db)
+    .fetch_all(db)
     .await?;
 
     for run in runs_untagged.into_iter() {
-        let tags = sqlx::query!(
-            "
-    SELECT tag.*
-    FROM tag
-    JOIN run_tag ON run_tag.tag_id = tag.id
-    WHERE run_id = ?;
-                ",
-            run.id,
-        )
-        .map(|row| Tag {
-            id: row.id,
-            value: row.value,
-        })
-        .fetch_all(&db)
-        .await?;
+        let tags = get_run_tags(db, run.id).await?;
 
         runs.push(Run {
             id: run.id,
             name: run.name,
             created_at: run.created_at.parse()?,
             tags,
+            status: run.status.parse()?,
+            finished_at: run.finished_at.map(|at| at.parse()).transpose()?,
+            pinned: run.pinned,
+            metadata: RunMetadata {
+                vcs_revision: run.vcs_revision,
+                branch: run.branch,
+                pull_request: run.pull_request,
+                ci_build_url: run.ci_build_url,
+                environment: serde_json::from_str(&run.environment)?,
+            },
         })
     }
     Ok(runs)
 }
 
+pub async fn count_runs(db: &Pool<Sqlite>, tag: Option<&str>) -> Result<i64> {
+    Ok(sqlx::query!(
+        r#"
+    SELECT COUNT(*) AS "count!: i64"
+    FROM run
+    WHERE $1 IS NULL OR EXISTS(
+        SELECT 1
+        FROM run_tag
+        JOIN tag ON tag.id = run_tag.tag_id
+        WHERE run_tag.run_id = run.id AND tag.value = $1
+    )
+            "#,
+        tag
+    )
+    .fetch_one(db)
+    .await?
+    .count)
+}
+
+pub async fn get_run(db: &Pool<Sqlite>, run_id: i64) -> Result<Run> {
+    let run = sqlx::query!(
+        "
+    SELECT *
+    FROM run
+    WHERE id = $1
+            ",
+        run_id
+    )
+    .fetch_one(db)
+    .await
+    .with_context(|| format!("run {run_id} not found"))?;
+
+    Ok(Run {
+        id: run.id,
+        name: run.name,
+        created_at: run.created_at.parse()?,
+        tags: get_run_tags(db, run_id).await?,
+        status: run.status.parse()?,
+        finished_at: run.finished_at.map(|at| at.parse()).transpose()?,
+        pinned: run.pinned,
+        metadata: RunMetadata {
+            vcs_revision: run.vcs_revision,
+            branch: run.branch,
+            pull_request: run.pull_request,
+            ci_build_url: run.ci_build_url,
+            environment: serde_json::from_str(&run.environment)?,
+        },
+    })
+}
+
+/// Moves a run out of `in_progress`, stamping `finished_at`
+pub async fn finish_run(db: &Pool<Sqlite>, run_id: i64, status: RunStatus) -> Result<Run> {
+    if !status.is_finished() {
+        return Err(ErrorKind::Validation
+            .error("a run can only be finished as completed, failed or aborted"));
+    }
+
+    let now = Utc::now().to_string();
+    let new_status = status.to_string();
+    let in_progress = RunStatus::InProgress.to_string();
+
+    // Only in-progress runs move on, so a run is finalized exactly once
+    let result = sqlx::query!(
+        "
+    UPDATE run
+    SET status = $1, finished_at = $2
+    WHERE id = $3 AND status = $4
+            ",
+        new_status,
+        now,
+        run_id,
+        in_progress
+    )
+    .execute(db)
+    .await?;
+
+    let run = get_run(db, run_id).await?;
+    if result.rows_affected() == 0 {
+        return Err(ErrorKind::Conflict.error(format!("run {run_id} is already {}", run.status)));
+    }
+    Ok(run)
+}
+
+pub async fn set_run_pinned(db: &Pool<Sqlite>, run_id: i64, pinned: bool) -> Result<Run> {
+    sqlx::query!(
+        "
+    UPDATE run
+    SET pinned = $1
+    WHERE id = $2
+            ",
+        pinned,
+        run_id
+    )
+    .execute(db)
+    .await?;
+
+    get_run(db, run_id).await
+}
+
+/// Metadata given for a run that already exists is merged into what is stored
 pub async fn insert_and_get_run(
-    db: &Pool<Sqlite>,
+    conn: &mut SqliteConnection,
     name: &str,
     tag_values: &[String],
+    metadata: Option<RunMetadata>,
 ) -> Result<Run> {
+    if let Some(metadata) = &metadata {
+        metadata.validate()?;
+    }
     let now = Utc::now().to_string();
 
     sqlx::query!(
@@ -189,7 +796,7 @@ pub async fn insert_and_get_run(
         name,
         now
     )
-    .execute(db)
+    .execute(&mut *conn)
     .await
     .ok();
 
@@ -201,12 +808,20 @@ pub async fn insert_and_get_run(
             ",
         name,
     )
-    .fetch_one(db)
+    .fetch_one(&mut *conn)
     .await?;
 
+    // A finished run is what comparisons and retention rely on, it must not change anymore
+    let status: RunStatus = run.status.parse()?;
+    if status.is_finished() {
+        return Err(ErrorKind::Conflict.error(format!(
+            "run `{name}` is already {status} and takes no more uploads"
+        )));
+    }
+
     let mut tags = vec![];
     for tag in tag_values {
-        let tag = insert_and_get_tag(db, tag).await?;
+        let tag = insert_and_get_tag(conn, tag).await?;
 
         sqlx::query!(
             "
@@ -216,22 +831,54 @@ pub async fn insert_and_get_run(
             run.id,
             tag.id
         )
-        .execute(db)
+        .execute(&mut *conn)
         .await
         .ok();
 
         tags.push(tag);
     }
 
+    let mut stored_metadata = RunMetadata {
+        vcs_revision: run.vcs_revision,
+        branch: run.branch,
+        pull_request: run.pull_request,
+        ci_build_url: run.ci_build_url,
+        environment: serde_json::from_str(&run.environment)?,
+    };
+    if let Some(metadata) = metadata {
+        stored_metadata.merge(metadata);
+        let environment = serde_json::to_string(&stored_metadata.environment)?;
+
+        sqlx::query!(
+            "
+        UPDATE run
+        SET vcs_revision = $1, branch = $2, pull_request = $3, ci_build_url = $4, environment = $5
+        WHERE id = $6
+                    ",
+            stored_metadata.vcs_revision,
+            stored_metadata.branch,
+            stored_metadata.pull_request,
+            stored_metadata.ci_build_url,
+            environment,
+            run.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
     Ok(Run {
         id: run.id,
         name: run.name,
         created_at: run.created_at.parse()?,
         tags,
+        status: run.status.parse()?,
+        finished_at: run.finished_at.map(|at| at.parse()).transpose()?,
+        pinned: run.pinned,
+        metadata: stored_metadata,
     })
 }
 
-pub async fn insert_and_get_tag(db: &Pool<Sqlite>, tag: &str) -> Result<Tag> {
+pub async fn insert_and_get_tag(conn: &mut SqliteConnection, tag: &str) -> Result<Tag> {
     sqlx::query!(
         "
     INSERT INTO tag(value)
@@ -239,7 +886,7 @@ pub async fn insert_and_get_tag(db: &Pool<Sqlite>, tag: &str) -> Result<Tag> {
                 ",
         tag,
     )
-    .execute(db)
+    .execute(&mut *conn)
     .await
     .ok();
 
@@ -255,32 +902,93 @@ pub async fn insert_and_get_tag(db: &Pool<Sqlite>, tag: &str) -> Result<Tag> {
         id: row.id,
         value: row.value,
     })
-    .fetch_one(db)
+    .fetch_one(&mut *conn)
     .await?)
 }
 
 pub async fn insert_and_get_test_case(
-    db: &Pool<Sqlite>,
+    conn: &mut SqliteConnection,
     run_id: i64,
     name: &str,
-    ignore_areas: Vec<((u32, u32), (u32, u32))>,
+    ignore_areas: Vec<IgnoreArea>,
+    comparison_settings: ComparisonSettings,
+    tag_values: &[String],
+    on_conflict: ConflictPolicy,
 ) -> Result<TestCase> {
     let now = Utc::now().to_string();
-    let ignore_areas = serde_json::to_string(&ignore_areas)?;
 
-    sqlx::query!(
+    let existing = sqlx::query!(
         "
-    INSERT INTO test_case(run_id,name,created_at,ignore_areas)
-    VALUES (?, ?, ?, ?);
-                ",
-        run_id,
+    SELECT id, ignore_areas, comparison_settings
+    FROM test_case
+    WHERE test_case.name = ? and run_id = ?
+            ",
         name,
-        now,
-        ignore_areas
+        run_id
     )
-    .execute(db)
-    .await
-    .ok();
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match existing {
+        None => {
+            let ignore_areas = serde_json::to_string(&ignore_areas)?;
+            let comparison_settings = serde_json::to_string(&comparison_settings)?;
+            sqlx::query!(
+                "
+    INSERT INTO test_case(run_id,name,created_at,ignore_areas,comparison_settings)
+    VALUES (?, ?, ?, ?, ?);
+                ",
+                run_id,
+                name,
+                now,
+                ignore_areas,
+                comparison_settings
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        Some(existing) => {
+            let mut stored: Vec<IgnoreArea> = serde_json::from_str(&existing.ignore_areas)?;
+            let mut stored_settings: ComparisonSettings =
+                serde_json::from_str(&existing.comparison_settings)?;
+            let differs = stored != ignore_areas || stored_settings != comparison_settings;
+            let update = match on_conflict {
+                // Uploaders send the ignore areas with every step, so rejecting only applies to
+                // screenshots and the test case keeps what its first step came with
+                ConflictPolicy::Reject => false,
+                ConflictPolicy::Overwrite => {
+                    stored = ignore_areas;
+                    stored_settings = comparison_settings;
+                    differs
+                }
+                ConflictPolicy::KeepBoth => {
+                    for area in ignore_areas {
+                        if !stored.contains(&area) {
+                            stored.push(area);
+                        }
+                    }
+                    stored_settings = stored_settings.or(comparison_settings);
+                    differs
+                }
+            };
+            if update {
+                let stored = serde_json::to_string(&stored)?;
+                let stored_settings = serde_json::to_string(&stored_settings)?;
+                sqlx::query!(
+                    "
+    UPDATE test_case
+    SET ignore_areas = ?, comparison_settings = ?
+    WHERE id = ?
+                ",
+                    stored,
+                    stored_settings,
+                    existing.id
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+    }
 
     let test_case = sqlx::query!(
         "
@@ -291,62 +999,319 @@ pub async fn insert_and_get_test_case(
         name,
         run_id
     )
-    .fetch_one(db)
+    .fetch_one(&mut *conn)
     .await?;
 
+    for tag in tag_values {
+        let tag = insert_and_get_tag(conn, tag).await?;
+
+        sqlx::query!(
+            "
+        INSERT OR IGNORE INTO test_case_tag(test_case_id,tag_id)
+        VALUES ($1, $2);
+                    ",
+            test_case.id,
+            tag.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
     Ok(TestCase {
         id: test_case.id,
         run_id,
         name: test_case.name,
         ignore_areas: serde_json::from_str(test_case.ignore_areas.as_str())?,
+        comparison_settings: serde_json::from_str(test_case.comparison_settings.as_str())?,
         created_at: test_case.created_at.parse()?,
+        tags: get_test_case_tags(&mut *conn, test_case.id).await?,
     })
 }
 
+/// Objects written for the screenshot are added to `stored`, see [`Storage::put`]
+#[allow(clippy::too_many_arguments)]
 pub async fn insert_and_get_step(
-    db: &Pool<Sqlite>,
+    conn: &mut SqliteConnection,
+    storage: &Storage,
     test_case_id: i64,
     name: &str,
-    img_base64_url: &str,
+    image: &Blob,
     parent_step_id: Option<i64>,
+    tag_values: &[String],
+    capture: &CaptureMetadata,
+    on_conflict: ConflictPolicy,
+    stored: &mut Vec<String>,
 ) -> Result<Step> {
     let now = Utc::now().to_string();
+    let capture = serde_json::to_string(capture)?;
 
-    sqlx::query!(
+    // Step trees never span test cases, deleting one of them would trip over the other
+    if let Some(parent_step_id) = parent_step_id {
+        let parent = sqlx::query!(
+            "
+    SELECT id
+    FROM step
+    WHERE id = ? AND test_case_id = ?
+            ",
+            parent_step_id,
+            test_case_id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        if parent.is_none() {
+            return Err(ErrorKind::Validation.error(format!(
+                "parent step {parent_step_id} is not a step of test case {test_case_id}"
+            )));
+        }
+    }
+
+    let existing = sqlx::query!(
         "
-    INSERT INTO step(test_case_id,parent_step_id,name,created_at,data_uri)
-    VALUES (?, ?, ?, ?, ?);
-                ",
-        test_case_id,
-        parent_step_id,
+    SELECT id, image_hash
+    FROM step
+    WHERE step.name = ? and test_case_id = ?
+            ",
         name,
-        now,
-        img_base64_url,
+        test_case_id
     )
-    .execute(db)
-    .await
-    .ok();
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    // The screenshot is only stored once it is clear the upload is taken,
+    // so that rejected ones don't leave it behind
+    match existing {
+        None => {
+            storage.put(conn, image, stored).await?;
+            sqlx::query!(
+                "
+    INSERT INTO step(test_case_id,parent_step_id,name,created_at,data_uri,image_hash,capture)
+    VALUES (?, ?, ?, ?, '', ?, ?);
+                ",
+                test_case_id,
+                parent_step_id,
+                name,
+                now,
+                image.hash,
+                capture,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        // Re-uploading the very same screenshot is a harmless retry
+        Some(existing) if existing.image_hash.as_ref() == Some(&image.hash) => {}
+        Some(existing) => match on_conflict {
+            ConflictPolicy::Reject => {
+                return Err(ErrorKind::Conflict.error(format!(
+                    "step `{name}` already exists in test case {test_case_id} with a different screenshot"
+                )));
+            }
+            ConflictPolicy::Overwrite => {
+                storage.put(conn, image, stored).await?;
+                sqlx::query!(
+                    "
+    UPDATE step
+    SET image_hash = ?, capture = ?, created_at = ?
+    WHERE id = ?
+                ",
+                    image.hash,
+                    capture,
+                    now,
+                    existing.id
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            ConflictPolicy::KeepBoth => {
+                storage.put(conn, image, stored).await?;
+                sqlx::query!(
+                    "
+    INSERT INTO step_attempt(step_id,attempt,data_uri,image_hash,capture,created_at)
+    SELECT id, attempt, '', image_hash, capture, created_at
+    FROM step
+    WHERE id = ?;
+                ",
+                    existing.id
+                )
+                .execute(&mut *conn)
+                .await?;
+
+                sqlx::query!(
+                    "
+    UPDATE step
+    SET image_hash = ?, capture = ?, created_at = ?, attempt = attempt + 1
+    WHERE id = ?
+                ",
+                    image.hash,
+                    capture,
+                    now,
+                    existing.id
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        },
+    }
 
     let step = sqlx::query!(
-        "
-    SELECT *
+        r#"
+    SELECT id, name, image_hash AS "image_hash!", attempt, created_at
     FROM step
     WHERE step.name = ? and test_case_id = ?
-            ",
+            "#,
         name,
         test_case_id
     )
-    .fetch_one(db)
+    .fetch_one(&mut *conn)
     .await?;
 
-    let children_steps = get_steps(db, test_case_id, step.id.into()).await?;
+    for tag in tag_values {
+        let tag = insert_and_get_tag(conn, tag).await?;
+
+        sqlx::query!(
+            "
+        INSERT OR IGNORE INTO step_tag(step_id,tag_id)
+        VALUES ($1, $2);
+                    ",
+            step.id,
+            tag.id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    let children_steps = get_steps(conn, test_case_id, step.id.into()).await?;
 
     Ok(Step {
         id: step.id,
         name: step.name,
         test_case_id,
-        data_uri: step.data_uri,
+        tags: get_step_tags(&mut *conn, step.id).await?,
+        image_hash: step.image_hash,
+        attempt: step.attempt,
         created_at: step.created_at.parse()?,
         children_steps,
     })
 }
+
+async fn delete_test_case_rows(conn: &mut SqliteConnection, test_case_id: i64) -> Result<u64> {
+    sqlx::query!(
+        "
+    DELETE FROM step_tag
+    WHERE step_id IN (SELECT id FROM step WHERE test_case_id = $1)
+            ",
+        test_case_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "
+    DELETE FROM step_attempt
+    WHERE step_id IN (SELECT id FROM step WHERE test_case_id = $1)
+            ",
+        test_case_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // Children point at their parents, so the whole tree has to go in one statement
+    sqlx::query!(
+        "
+    DELETE FROM step
+    WHERE test_case_id = $1
+            ",
+        test_case_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "
+    DELETE FROM test_case_tag
+    WHERE test_case_id = $1
+            ",
+        test_case_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(sqlx::query!(
+        "
+    DELETE FROM test_case
+    WHERE id = $1
+            ",
+        test_case_id
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected())
+}
+
+/// Removes the test case with its steps, their screenshots and all tag links
+pub async fn delete_test_case(
+    db: &Pool<Sqlite>,
+    storage: &Storage,
+    test_case_id: i64,
+) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    if delete_test_case_rows(&mut tx, test_case_id).await? == 0 {
+        return Err(ErrorKind::NotFound.error(format!("test case {test_case_id} not found")));
+    }
+    let orphans = delete_orphaned_blobs(&mut tx).await?;
+
+    tx.commit().await?;
+    storage.forget(&orphans).await;
+    Ok(())
+}
+
+/// Removes the run with everything uploaded into it
+pub async fn delete_run(db: &Pool<Sqlite>, storage: &Storage, run_id: i64) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    let test_case_ids = sqlx::query!(
+        "
+    SELECT id
+    FROM test_case
+    WHERE run_id = $1
+            ",
+        run_id
+    )
+    .map(|row| row.id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for test_case_id in test_case_ids {
+        delete_test_case_rows(&mut tx, test_case_id).await?;
+    }
+
+    sqlx::query!(
+        "
+    DELETE FROM run_tag
+    WHERE run_id = $1
+            ",
+        run_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let deleted = sqlx::query!(
+        "
+    DELETE FROM run
+    WHERE id = $1
+            ",
+        run_id
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(ErrorKind::NotFound.error(format!("run {run_id} not found")));
+    }
+    let orphans = delete_orphaned_blobs(&mut tx).await?;
+
+    tx.commit().await?;
+    storage.forget(&orphans).await;
+    Ok(())
+}
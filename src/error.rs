@@ -1,30 +1,217 @@
+use std::fmt::Debug;
+use std::fmt::Display;
+
+use anyhow::anyhow;
+use axum::async_trait;
+use axum::body::Body;
+use axum::extract::multipart::MultipartError;
+use axum::extract::multipart::MultipartRejection;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::rejection::PathRejection;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::FromRequest;
+use axum::extract::FromRequestParts;
+use axum::extract::Multipart;
+use axum::http::Request;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::response::Response;
+use axum::Json;
+use serde::Serialize;
 
 pub type HttpResult<T = ()> = Result<T, HttpError>;
 
+/// Machine-readable category of a failure, sent to clients as `error.code`.
+///
+/// Deeper layers that return `anyhow::Result` pick a kind with [`ErrorKind::error`].
+#[derive(Debug, Clone, Copy, Serialize, strum::Display, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Validation,
+    NotFound,
+    Conflict,
+    Storage,
+    Internal,
+}
+
+impl std::error::Error for ErrorKind {}
+
+impl ErrorKind {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorKind::Validation => StatusCode::BAD_REQUEST,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::Conflict => StatusCode::CONFLICT,
+            ErrorKind::Storage => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// An `anyhow::Error` carrying this kind, for code that doesn't deal in [`HttpError`]s
+    pub fn error(self, message: impl Display + Send + Sync + 'static) -> anyhow::Error {
+        anyhow::Error::new(self).context(message)
+    }
+
+    fn classify(err: &anyhow::Error) -> ErrorKind {
+        if let Some(kind) = err.downcast_ref::<ErrorKind>() {
+            return *kind;
+        }
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<sqlx::Error>() {
+                return match err {
+                    sqlx::Error::RowNotFound => ErrorKind::NotFound,
+                    sqlx::Error::Database(db_err) => match db_err.kind() {
+                        sqlx::error::ErrorKind::UniqueViolation => ErrorKind::Conflict,
+                        sqlx::error::ErrorKind::ForeignKeyViolation
+                        | sqlx::error::ErrorKind::NotNullViolation
+                        | sqlx::error::ErrorKind::CheckViolation => ErrorKind::Validation,
+                        _ => ErrorKind::Storage,
+                    },
+                    sqlx::Error::Io(_)
+                    | sqlx::Error::PoolTimedOut
+                    | sqlx::Error::PoolClosed
+                    | sqlx::Error::WorkerCrashed => ErrorKind::Storage,
+                    _ => ErrorKind::Internal,
+                };
+            }
+            if cause.is::<std::io::Error>() {
+                return ErrorKind::Storage;
+            }
+            if cause.is::<JsonRejection>()
+                || cause.is::<PathRejection>()
+                || cause.is::<QueryRejection>()
+                || cause.is::<MultipartRejection>()
+                || cause.is::<MultipartError>()
+                || cause.is::<serde_json::Error>()
+                || cause.is::<base64::DecodeError>()
+                || cause.is::<image::ImageError>()
+                || cause.is::<std::num::ParseIntError>()
+            {
+                return ErrorKind::Validation;
+            }
+        }
+        ErrorKind::Internal
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorResBody {
+    error: ErrorResBodyInner,
+}
+
+#[derive(Serialize)]
+struct ErrorResBodyInner {
+    code: ErrorKind,
+    message: String,
+}
+
 // Make our own error that wraps `anyhow::Error`.
-pub struct HttpError(anyhow::Error);
+#[derive(Debug)]
+pub struct HttpError {
+    kind: ErrorKind,
+    err: anyhow::Error,
+}
+
+impl HttpError {
+    pub fn new(kind: ErrorKind, err: impl Into<anyhow::Error>) -> Self {
+        Self {
+            kind,
+            err: err.into(),
+        }
+    }
+
+    pub fn validation(message: impl Display + Debug + Send + Sync + 'static) -> Self {
+        Self::new(ErrorKind::Validation, anyhow!(message))
+    }
 
-// Tell axum how to convert `AppError` into a response.
+    pub fn not_found(message: impl Display + Debug + Send + Sync + 'static) -> Self {
+        Self::new(ErrorKind::NotFound, anyhow!(message))
+    }
+
+    pub fn conflict(message: impl Display + Debug + Send + Sync + 'static) -> Self {
+        Self::new(ErrorKind::Conflict, anyhow!(message))
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+// Tell axum how to convert `HttpError` into a response.
 impl IntoResponse for HttpError {
     fn into_response(self) -> Response {
+        // Extractor rejections repeat their source in their own message, so skip what is already said
+        let mut message = String::new();
+        for cause in self.err.chain().filter(|cause| !cause.is::<ErrorKind>()) {
+            let cause = cause.to_string();
+            if message.contains(&cause) {
+                continue;
+            }
+            if !message.is_empty() {
+                message.push_str(": ");
+            }
+            message.push_str(&cause);
+        }
+
+        if matches!(self.kind, ErrorKind::Storage | ErrorKind::Internal) {
+            log::error!("{message}");
+        }
+
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
+            self.kind.status_code(),
+            Json(ErrorResBody {
+                error: ErrorResBodyInner {
+                    code: self.kind,
+                    message,
+                },
+            }),
         )
             .into_response()
     }
 }
 
 // This enables using `?` on functions that return `Result<_, anyhow::Error>` to turn them into
-// `Result<_, AppError>`. That way you don't need to do that manually.
+// `Result<_, HttpError>`. That way you don't need to do that manually.
 impl<E> From<E> for HttpError
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        let err = err.into();
+        Self {
+            kind: ErrorKind::classify(&err),
+            err,
+        }
+    }
+}
+
+/// `axum::Json` whose rejections are reported as [`HttpError`]s
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(HttpError))]
+pub struct JsonBody<T>(pub T);
+
+/// `axum::extract::Path` whose rejections are reported as [`HttpError`]s
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(HttpError))]
+pub struct PathParams<T>(pub T);
+
+/// `axum::extract::Query` whose rejections are reported as [`HttpError`]s
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(HttpError))]
+pub struct QueryParams<T>(pub T);
+
+/// `axum::extract::Multipart` whose rejections are reported as [`HttpError`]s
+pub struct MultipartBody(pub Multipart);
+
+#[async_trait]
+impl<S> FromRequest<S, Body> for MultipartBody
+where
+    S: Send + Sync,
+{
+    type Rejection = HttpError;
+
+    async fn from_request(req: Request<Body>, state: &S) -> HttpResult<Self> {
+        Ok(MultipartBody(Multipart::from_request(req, state).await?))
     }
 }
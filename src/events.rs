@@ -0,0 +1,67 @@
+use std::convert::Infallible;
+
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
+use axum::routing::get;
+use axum::Extension;
+use axum::Router;
+use futures::Stream;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::models::ingest_event::IngestEvent;
+
+/// How many events a slow subscriber may fall behind before it starts missing some
+const CAPACITY: usize = 1024;
+
+/// Fans ingestion events out to every open `/api/events` stream
+#[derive(Debug, Clone)]
+pub struct Events(broadcast::Sender<IngestEvent>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Events(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Events {
+    pub fn publish(&self, event: IngestEvent) {
+        // Nobody listening is fine
+        self.0.send(event).ok();
+    }
+
+    pub fn publish_all(&self, events: impl IntoIterator<Item = IngestEvent>) {
+        for event in events {
+            self.publish(event);
+        }
+    }
+}
+
+/// Every event as JSON in the `data` of an unnamed SSE message, lagging subscribers skip ahead
+async fn events(
+    Extension(events): Extension<Events>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = futures::stream::unfold(events.0.subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let event = Event::default()
+                        .json_data(&event)
+                        .unwrap_or_else(|_| Event::default().comment("unserializable event"));
+                    return Some((Ok(event), receiver));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("event subscriber lagged behind, skipped {skipped} events");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub fn router() -> Router {
+    Router::new().route("/", get(events))
+}
@@ -4,7 +4,6 @@ use std::collections::BTreeMap;
 
 use anyhow::Result;
 use askama::Template;
-use axum::extract::Query;
 use axum::extract::State;
 use axum::response::Html;
 use axum::routing::get;
@@ -14,6 +13,7 @@ use sqlx::Pool;
 use sqlx::Sqlite;
 
 use crate::error::HttpResult;
+use crate::error::QueryParams;
 use crate::models::side::Side;
 
 use self::components::choose_a_run;
@@ -26,7 +26,7 @@ struct TemplateInstance {
 }
 
 #[derive(Deserialize, Debug)]
-pub struct QueryParams {
+pub struct IndexQueryParams {
     left_run: Option<i64>,
     right_run: Option<i64>,
 }
@@ -50,11 +50,11 @@ async fn side(
 
 async fn html(
     State(db): State<Pool<Sqlite>>,
-    Query(all_qp): Query<BTreeMap<String, String>>,
-    Query(QueryParams {
+    QueryParams(all_qp): QueryParams<BTreeMap<String, String>>,
+    QueryParams(IndexQueryParams {
         left_run,
         right_run,
-    }): Query<QueryParams>,
+    }): QueryParams<IndexQueryParams>,
 ) -> HttpResult<Html<String>> {
     let left = side(db.clone(), all_qp.clone(), Side::Left, left_run).await?;
     let right = side(db.clone(), all_qp.clone(), Side::Right, right_run).await?;
@@ -12,6 +12,7 @@ use crate::models::side::Side;
 #[derive(Template)]
 #[template(path = "frontend/pages/index/components/choose_a_run.jinja")]
 pub struct TemplateInstance {
+    side: Side,
     runs: Vec<(Run, String)>,
 }
 
@@ -60,6 +61,6 @@ impl TemplateInstance {
             })
             .collect();
 
-        Ok(TemplateInstance { runs })
+        Ok(TemplateInstance { side, runs })
     }
 }
@@ -2,31 +2,58 @@ use std::collections::HashMap;
 use std::fmt::Write;
 use std::ops::AddAssign;
 
+use anyhow::Result;
 use askama::Template;
-use axum::extract::Path;
 use axum::extract::State;
 use axum::response::Html;
 use axum::routing::get;
 use axum::Router;
+use serde::Deserialize;
+use serde::Serialize;
 use similar::TextDiff;
 use sqlx::Pool;
 use sqlx::Sqlite;
 use velcro::hash_map;
 
 use crate::db::get_case_with_steps;
+use crate::db::get_run;
 use crate::db::get_run_test_cases;
+use crate::db::test_case_has_tag;
 use crate::error::HttpResult;
+use crate::error::PathParams;
+use crate::error::QueryParams;
+use crate::models::run::Run;
 use crate::models::side::Side;
 use crate::models::step::Step;
 use crate::models::test_case::TestCase;
 use crate::models::test_case::TestCaseWithSteps;
+use crate::services::match_test_cases;
+use crate::services::TestCaseMatches;
 
 #[derive(Template)]
 #[template(path = "frontend/pages/runs.jinja", escape = "none")]
 struct TemplateInstance {
+    left_run: Run,
+    right_run: Run,
+    tag: Option<String>,
     raw_templates: String,
+    /// [`DiffData`] as JSON, the page reads it again from fresh copies of itself on live updates
+    diff_data: String,
+}
+
+#[derive(Serialize)]
+struct DiffData {
     diff: String,
-    map: String,
+    map: HashMap<String, HashMap<Side, HashMap<usize, i64>>>,
+    case_tags: HashMap<String, Vec<String>>,
+    /// Test case ids by name and side, for deleting them from the page
+    case_ids: HashMap<String, HashMap<Side, i64>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RunQueryParams {
+    /// Only show test cases where the case or one of its steps has this tag
+    tag: Option<String>,
 }
 
 fn write_in_steps(
@@ -35,19 +62,24 @@ fn write_in_steps(
     line: &mut usize,
     steps: &[Step],
     ident: usize,
-) {
+) -> std::fmt::Result {
     for step in steps {
         for _ in 0..ident {
-            write!(w, "    ").unwrap();
+            write!(w, "    ")?;
         }
-        writeln!(w, "{}", step.name).unwrap();
+        write!(w, "{}", step.name)?;
+        for tag in &step.tags {
+            write!(w, " #{}", tag.value)?;
+        }
+        writeln!(w)?;
         line.add_assign(1);
         line_id_map.insert(*line, step.id);
-        write_in_steps(w, line_id_map, line, &step.children_steps, ident + 1);
+        write_in_steps(w, line_id_map, line, &step.children_steps, ident + 1)?;
     }
+    Ok(())
 }
 
-fn case_to_string(test_case: &TestCaseWithSteps) -> (String, HashMap<usize, i64>) {
+fn case_to_string(test_case: &TestCaseWithSteps) -> Result<(String, HashMap<usize, i64>)> {
     let mut result = "".to_string();
     let mut line_id_map = Default::default();
     let mut line = 0;
@@ -57,30 +89,66 @@ fn case_to_string(test_case: &TestCaseWithSteps) -> (String, HashMap<usize, i64>
         &mut line,
         &test_case.steps,
         0,
-    );
-    (result, line_id_map)
+    )?;
+    Ok((result, line_id_map))
+}
+
+async fn filter_by_tag(
+    db: &Pool<Sqlite>,
+    test_cases: Vec<TestCase>,
+    tag: &str,
+) -> Result<Vec<TestCase>> {
+    let mut filtered = vec![];
+    for test_case in test_cases {
+        if test_case_has_tag(db, test_case.id, tag).await? {
+            filtered.push(test_case);
+        }
+    }
+    Ok(filtered)
 }
 
 pub async fn html(
     State(db): State<Pool<Sqlite>>,
-    Path((left_run, right_run)): Path<(i64, i64)>,
+    PathParams((left_run_id, right_run_id)): PathParams<(i64, i64)>,
+    QueryParams(RunQueryParams { tag }): QueryParams<RunQueryParams>,
 ) -> HttpResult<Html<String>> {
-    let left_cases = get_run_test_cases(&db, left_run).await?;
-    let mut right_cases = get_run_test_cases(&db, right_run).await?;
-
-    let mut matches: Vec<(TestCase, TestCase)> = vec![];
-    let mut left_loners: Vec<TestCase> = vec![];
-
-    for l in left_cases.iter() {
-        if let Some(pos) = right_cases.iter_mut().position(|r| l.name == r.name) {
-            let r = right_cases.remove(pos);
-            matches.push((l.clone(), r));
-        } else {
-            left_loners.push(l.clone());
+    let left_run = get_run(&db, left_run_id).await?;
+    let right_run = get_run(&db, right_run_id).await?;
+
+    let mut left_cases = get_run_test_cases(&db, left_run_id).await?;
+    let mut right_cases = get_run_test_cases(&db, right_run_id).await?;
+
+    if let Some(tag) = &tag {
+        left_cases = filter_by_tag(&db, left_cases, tag).await?;
+        right_cases = filter_by_tag(&db, right_cases, tag).await?;
+    }
+
+    let case_tags: HashMap<String, Vec<String>> = left_cases
+        .iter()
+        .chain(right_cases.iter())
+        .map(|test_case| {
+            (
+                test_case.name.clone(),
+                test_case.tags.iter().map(|tag| tag.value.clone()).collect(),
+            )
+        })
+        .collect();
+
+    let mut case_ids: HashMap<String, HashMap<Side, i64>> = HashMap::new();
+    for (side, test_cases) in [(Side::Left, &left_cases), (Side::Right, &right_cases)] {
+        for test_case in test_cases {
+            case_ids
+                .entry(test_case.name.clone())
+                .or_default()
+                .insert(side, test_case.id);
         }
     }
 
-    let right_loners: Vec<TestCase> = right_cases;
+    let TestCaseMatches {
+        matches,
+        left_loners,
+        right_loners,
+    } = match_test_cases(left_cases, right_cases, |test_case| &test_case.name);
 
     let mut diffs: String = String::default();
     let mut file_name_lines_id_map: HashMap<String, HashMap<Side, HashMap<usize, i64>>> =
@@ -88,15 +156,15 @@ pub async fn html(
 
     for test_case in left_loners.into_iter() {
         let case_with_steps = get_case_with_steps(&db, test_case.id).await?;
-        let (content, line_id_map) = case_to_string(&case_with_steps);
+        let (content, line_id_map) = case_to_string(&case_with_steps)?;
         file_name_lines_id_map.insert(test_case.name.clone(), hash_map! {Side::Left: line_id_map});
 
         let mut hunk = String::default();
-        writeln!(&mut hunk, "--- {}", test_case.name).unwrap();
-        writeln!(&mut hunk, "+++ {}", test_case.name).unwrap();
-        writeln!(&mut hunk, "@@ @@").unwrap();
+        writeln!(&mut hunk, "--- {}", test_case.name)?;
+        writeln!(&mut hunk, "+++ {}", test_case.name)?;
+        writeln!(&mut hunk, "@@ @@")?;
         for line in content.lines() {
-            writeln!(&mut hunk, "- {line}").unwrap();
+            writeln!(&mut hunk, "- {line}")?;
         }
 
         diffs += hunk.to_string().as_str();
@@ -104,15 +172,15 @@ pub async fn html(
 
     for test_case in right_loners.into_iter() {
         let case_with_steps = get_case_with_steps(&db, test_case.id).await?;
-        let (content, line_id_map) = case_to_string(&case_with_steps);
+        let (content, line_id_map) = case_to_string(&case_with_steps)?;
         file_name_lines_id_map.insert(test_case.name.clone(), hash_map! {Side::Right: line_id_map});
 
         let mut hunk = String::default();
-        writeln!(&mut hunk, "--- {}", test_case.name).unwrap();
-        writeln!(&mut hunk, "+++ {}", test_case.name).unwrap();
-        writeln!(&mut hunk, "@@ @@").unwrap();
+        writeln!(&mut hunk, "--- {}", test_case.name)?;
+        writeln!(&mut hunk, "+++ {}", test_case.name)?;
+        writeln!(&mut hunk, "@@ @@")?;
         for line in content.lines() {
-            writeln!(&mut hunk, "+ {line}").unwrap();
+            writeln!(&mut hunk, "+ {line}")?;
         }
 
         diffs += hunk.to_string().as_str();
@@ -124,8 +192,8 @@ pub async fn html(
         let l_case_with_steps = get_case_with_steps(&db, left_test_case.id).await?;
         let r_case_with_steps = get_case_with_steps(&db, right_test_case.id).await?;
 
-        let (l, l_line_id_map) = case_to_string(&l_case_with_steps);
-        let (r, r_line_id_map) = case_to_string(&r_case_with_steps);
+        let (l, l_line_id_map) = case_to_string(&l_case_with_steps)?;
+        let (r, r_line_id_map) = case_to_string(&r_case_with_steps)?;
 
         file_name_lines_id_map.insert(
             left_test_case.name.clone(),
@@ -135,13 +203,13 @@ pub async fn html(
             },
         );
 
-        writeln!(&mut hunk, "--- {}", left_test_case.name).unwrap();
-        writeln!(&mut hunk, "+++ {}", right_test_case.name).unwrap();
+        writeln!(&mut hunk, "--- {}", left_test_case.name)?;
+        writeln!(&mut hunk, "+++ {}", right_test_case.name)?;
 
         if l == r {
-            writeln!(&mut hunk, "@@ @@").unwrap();
+            writeln!(&mut hunk, "@@ @@")?;
             for line in l.lines() {
-                writeln!(&mut hunk, " {line}").unwrap();
+                writeln!(&mut hunk, " {line}")?;
             }
             diffs += hunk.to_string().as_str();
             continue;
@@ -156,7 +224,7 @@ pub async fn html(
             .into_iter()
             .map(move |ops| similar::udiff::UnifiedDiffHunk::new(ops, text_diff_ref, false))
         {
-            write!(&mut hunk, "{text_hunk}").unwrap();
+            write!(&mut hunk, "{text_hunk}")?;
         }
 
         diffs += hunk.to_string().as_str();
@@ -224,9 +292,18 @@ pub async fn html(
 
     Ok(Html(
         TemplateInstance {
-            diff: diffs,
+            left_run,
+            right_run,
+            tag,
             raw_templates,
-            map: serde_json::to_string(&file_name_lines_id_map)?,
+            // Keeps step names like `</script>` from closing the tag the JSON sits in
+            diff_data: serde_json::to_string(&DiffData {
+                diff: diffs,
+                map: file_name_lines_id_map,
+                case_tags,
+                case_ids,
+            })?
+            .replace("</", "<\\/"),
         }
         .render()?,
     ))
@@ -1,48 +1,100 @@
 use askama::Template;
-use axum::extract::Path;
 use axum::extract::State;
-use axum::http::header;
 use axum::http::HeaderMap;
 use axum::response::Html;
-use axum::response::IntoResponse;
+use axum::response::Response;
 use axum::routing::get;
+use axum::Extension;
 use axum::Router;
 use sqlx::Pool;
 use sqlx::Sqlite;
 
-use crate::db::get_step_data_uri_and_test_case_id;
+use crate::db::get_step_image_hash_and_test_case_id;
+use crate::db::get_step_tags;
 use crate::db::get_test_case;
 use crate::error::HttpResult;
+use crate::error::PathParams;
+use crate::images::conditional;
+use crate::models::blob::content_hash;
+use crate::models::capture_metadata::StepCapture;
+use crate::models::comparison_settings::ComparisonSettings;
 use crate::models::side::Side;
+use crate::models::tag::Tag;
 use crate::services::compare_steps;
+use crate::services::step_capture;
+use crate::services::step_comparison_settings;
+use crate::storage::Storage;
 
 #[derive(Template)]
 #[template(path = "frontend/pages/steps.jinja", escape = "none")]
 struct TemplateInstance {
     list: Vec<ListItem>,
+    /// Heads the capture table, one per step shown
+    capture_columns: Vec<String>,
+    capture_rows: Vec<CaptureRow>,
+}
+
+struct CaptureRow {
+    label: &'static str,
+    values: Vec<String>,
+    /// A different viewport or browser often explains a diff better than the pixels do
+    differs: bool,
+}
+
+/// One row per field that is known for any of the steps
+fn capture_rows(captures: &[StepCapture]) -> Vec<CaptureRow> {
+    let fields: Vec<_> = captures.iter().map(StepCapture::fields).collect();
+    let Some(first) = fields.first() else {
+        return vec![];
+    };
+
+    (0..first.len())
+        .filter_map(|index| {
+            let values: Vec<Option<String>> =
+                fields.iter().map(|field| field[index].1.clone()).collect();
+            if values.iter().all(Option::is_none) {
+                return None;
+            }
+            Some(CaptureRow {
+                label: first[index].0,
+                differs: values.windows(2).any(|pair| pair[0] != pair[1]),
+                values: values
+                    .into_iter()
+                    .map(|value| value.unwrap_or_else(|| "–".to_string()))
+                    .collect(),
+            })
+        })
+        .collect()
 }
 
 struct ListItem {
     unique_id: String,
-    data_uri: String,
+    image_url: String,
     cta: String,
     img_css: String,
+    tags: Vec<Tag>,
 }
 
 async fn html_single(
     State(db): State<Pool<Sqlite>>,
-    Path(step_id): Path<i64>,
+    Extension(storage): Extension<Storage>,
+    PathParams(step_id): PathParams<i64>,
 ) -> HttpResult<Html<String>> {
-    let (data_uri, _) = get_step_data_uri_and_test_case_id(step_id, &db).await?;
+    // Unknown steps are a 404 rather than a page with a broken image
+    get_step_image_hash_and_test_case_id(step_id, &db).await?;
+    let tags = get_step_tags(&db, step_id).await?;
 
     Ok(Html(
         TemplateInstance {
             list: vec![ListItem {
                 unique_id: "single".to_string(),
-                data_uri,
+                image_url: format!("/images/steps/{step_id}"),
                 cta: "🖼️".to_string(),
                 img_css: "".to_string(),
+                tags,
             }],
+            capture_columns: vec![],
+            capture_rows: capture_rows(&[step_capture(&db, &storage, step_id).await?]),
         }
         .render()?,
     ))
@@ -50,48 +102,90 @@ async fn html_single(
 
 async fn html_diff(
     State(db): State<Pool<Sqlite>>,
-    Path((left_step_id, right_step_id)): Path<(i64, i64)>,
-) -> HttpResult<(HeaderMap, impl IntoResponse)> {
-    let mut headers = HeaderMap::new();
-    headers.insert(header::CACHE_CONTROL, "public, max-age=31557600".parse()?);
-
-    let (left_data_uri, left_test_case_id) =
-        get_step_data_uri_and_test_case_id(left_step_id, &db).await?;
+    Extension(storage): Extension<Storage>,
+    Extension(global): Extension<ComparisonSettings>,
+    PathParams((left_step_id, right_step_id)): PathParams<(i64, i64)>,
+    headers: HeaderMap,
+) -> HttpResult<Response> {
+    let (left_image_hash, left_test_case_id) =
+        get_step_image_hash_and_test_case_id(left_step_id, &db).await?;
     let left_test_case = get_test_case(&db, left_test_case_id).await?;
-    let (right_data_uri, right_test_case_id) =
-        get_step_data_uri_and_test_case_id(right_step_id, &db).await?;
+    let (right_image_hash, right_test_case_id) =
+        get_step_image_hash_and_test_case_id(right_step_id, &db).await?;
     let right_test_case = get_test_case(&db, right_test_case_id).await?;
+    let settings = step_comparison_settings(&db, global, &left_test_case, &right_test_case).await?;
     let ignore_ranges = [left_test_case.ignore_areas, right_test_case.ignore_areas].concat();
+    let tags = [
+        get_step_tags(&db, left_step_id).await?,
+        get_step_tags(&db, right_step_id).await?,
+    ];
+    let captures = [
+        step_capture(&db, &storage, left_step_id).await?,
+        step_capture(&db, &storage, right_step_id).await?,
+    ];
+
+    // Besides the images and settings, the page shows the tags and capture details of both steps
+    let inputs = format!(
+        "steps:{left_image_hash}:{right_image_hash}:{}:{}:{}:{}",
+        serde_json::to_string(&ignore_ranges)?,
+        serde_json::to_string(&settings)?,
+        serde_json::to_string(&tags)?,
+        serde_json::to_string(&captures)?
+    );
+    let etag = format!("\"{}\"", content_hash(inputs.as_bytes()));
 
-    let (contains_changes, diff_data_uri) = compare_steps(
-        left_data_uri.as_str(),
-        right_data_uri.as_str(),
-        &ignore_ranges,
-    )
-    .await?;
+    conditional(&headers, etag, || async {
+        let left_blob = storage.blob(&left_image_hash).await?;
+        let right_blob = storage.blob(&right_image_hash).await?;
+        let comparison = compare_steps(
+            &left_blob.data,
+            &right_blob.data,
+            &ignore_ranges,
+            &settings,
+            false,
+        )
+        .await?;
 
-    let mut list = vec![];
-    list.push(ListItem {
-        unique_id: Side::Left.to_string(),
-        data_uri: left_data_uri,
-        cta: "👈".to_string(),
-        img_css: "".to_string(),
-    });
-    if contains_changes {
+        let [left_tags, right_tags] = tags;
+        let mut list = vec![];
+        list.push(ListItem {
+            unique_id: Side::Left.to_string(),
+            image_url: format!("/images/steps/{left_step_id}"),
+            cta: "👈".to_string(),
+            img_css: "".to_string(),
+            tags: left_tags,
+        });
+        if comparison.contains_changes {
+            list.push(ListItem {
+                unique_id: "diff".to_string(),
+                image_url: format!("/images/diffs/{left_step_id}/{right_step_id}"),
+                cta: "🤝".to_string(),
+                img_css: "invert".to_string(),
+                tags: vec![],
+            });
+            list.push(ListItem {
+                unique_id: "ssim".to_string(),
+                image_url: format!("/images/ssim/{left_step_id}/{right_step_id}"),
+                cta: "🌗".to_string(),
+                img_css: "invert".to_string(),
+                tags: vec![],
+            });
+        }
         list.push(ListItem {
-            unique_id: "diff".to_string(),
-            data_uri: diff_data_uri,
-            cta: "🤝".to_string(),
-            img_css: "invert".to_string(),
+            unique_id: Side::Right.to_string(),
+            image_url: format!("/images/steps/{right_step_id}"),
+            cta: "👉".to_string(),
+            img_css: "".to_string(),
+            tags: right_tags,
         });
-    }
-    list.push(ListItem {
-        unique_id: Side::Right.to_string(),
-        data_uri: right_data_uri,
-        cta: "👉".to_string(),
-        img_css: "".to_string(),
-    });
-    Ok((headers, Html(TemplateInstance { list }.render()?)))
+        let template = TemplateInstance {
+            list,
+            capture_columns: vec!["👈".to_string(), "👉".to_string()],
+            capture_rows: capture_rows(&captures),
+        };
+        Ok(Html(template.render()?))
+    })
+    .await
 }
 
 pub fn router(db: Pool<Sqlite>) -> Router {
@@ -0,0 +1,217 @@
+use axum::extract::State;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use axum::Router;
+use sqlx::Pool;
+use sqlx::Sqlite;
+
+use crate::db::get_step_image_hash_and_test_case_id;
+use crate::db::get_test_case;
+use crate::db::get_thumbnail;
+use crate::db::insert_thumbnail;
+use crate::error::HttpResult;
+use crate::error::PathParams;
+use crate::models::blob::content_hash;
+use crate::models::comparison_settings::ComparisonSettings;
+use crate::services::compare_steps;
+use crate::services::step_comparison_settings;
+use crate::services::thumbnail_png;
+use crate::storage::Storage;
+
+/// Steps can be overwritten, so browsers keep the images but ask whether they are still current
+const CACHE_CONTROL: &str = "no-cache";
+
+/// Whether `If-None-Match` names the version the client would get anyway
+fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(if_none_match) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// A 304 when the client is up to date, otherwise whatever `body` produces.
+/// Pages and API responses that depend on steps use it as well, since those can be overwritten.
+pub(crate) async fn conditional<F, T>(
+    headers: &HeaderMap,
+    etag: String,
+    body: impl FnOnce() -> F,
+) -> HttpResult<Response>
+where
+    F: std::future::Future<Output = HttpResult<T>>,
+    T: IntoResponse,
+{
+    let etag_value = HeaderValue::from_str(&etag)?;
+    let cache_control = HeaderValue::from_static(CACHE_CONTROL);
+
+    if is_fresh(headers, &etag) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::ETAG, etag_value),
+                (header::CACHE_CONTROL, cache_control),
+            ],
+        )
+            .into_response());
+    }
+
+    Ok((
+        [
+            (header::ETAG, etag_value),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        body().await?,
+    )
+        .into_response())
+}
+
+/// An image as a response body
+fn image(mime: &str, data: Vec<u8>) -> HttpResult<Response> {
+    Ok(([(header::CONTENT_TYPE, HeaderValue::from_str(mime)?)], data).into_response())
+}
+
+/// The screenshot as uploaded, its content hash is the ETag
+async fn step_image(
+    State(db): State<Pool<Sqlite>>,
+    Extension(storage): Extension<Storage>,
+    PathParams(step_id): PathParams<i64>,
+    headers: HeaderMap,
+) -> HttpResult<Response> {
+    let (image_hash, _) = get_step_image_hash_and_test_case_id(step_id, &db).await?;
+
+    conditional(&headers, format!("\"{image_hash}\""), || async {
+        let blob = storage.blob(&image_hash).await?;
+        image(&blob.mime, blob.data)
+    })
+    .await
+}
+
+/// Which visualization of a comparison a route serves
+#[derive(Debug, Clone, Copy, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+enum DiffView {
+    Diff,
+    SsimMap,
+}
+
+/// The diff PNG of two steps, only computed when the client doesn't have it yet.
+/// It only depends on both screenshots, the ignore areas and the comparison settings,
+/// so those make up the ETag.
+async fn diff_image(
+    State(db): State<Pool<Sqlite>>,
+    Extension(storage): Extension<Storage>,
+    Extension(global): Extension<ComparisonSettings>,
+    PathParams((left_step_id, right_step_id)): PathParams<(i64, i64)>,
+    headers: HeaderMap,
+) -> HttpResult<Response> {
+    comparison_image(
+        &db,
+        &storage,
+        global,
+        (left_step_id, right_step_id),
+        &headers,
+        DiffView::Diff,
+    )
+    .await
+}
+
+/// Same as [`diff_image`] for the SSIM map, where darker blocks are less alike
+async fn ssim_map(
+    State(db): State<Pool<Sqlite>>,
+    Extension(storage): Extension<Storage>,
+    Extension(global): Extension<ComparisonSettings>,
+    PathParams((left_step_id, right_step_id)): PathParams<(i64, i64)>,
+    headers: HeaderMap,
+) -> HttpResult<Response> {
+    comparison_image(
+        &db,
+        &storage,
+        global,
+        (left_step_id, right_step_id),
+        &headers,
+        DiffView::SsimMap,
+    )
+    .await
+}
+
+async fn comparison_image(
+    db: &Pool<Sqlite>,
+    storage: &Storage,
+    global: ComparisonSettings,
+    (left_step_id, right_step_id): (i64, i64),
+    headers: &HeaderMap,
+    view: DiffView,
+) -> HttpResult<Response> {
+    let (left_image_hash, left_test_case_id) =
+        get_step_image_hash_and_test_case_id(left_step_id, db).await?;
+    let left_test_case = get_test_case(db, left_test_case_id).await?;
+    let (right_image_hash, right_test_case_id) =
+        get_step_image_hash_and_test_case_id(right_step_id, db).await?;
+    let right_test_case = get_test_case(db, right_test_case_id).await?;
+    let settings = step_comparison_settings(db, global, &left_test_case, &right_test_case).await?;
+    let ignore_ranges = [left_test_case.ignore_areas, right_test_case.ignore_areas].concat();
+
+    let inputs = format!(
+        "{view}:{left_image_hash}:{right_image_hash}:{}:{}",
+        serde_json::to_string(&ignore_ranges)?,
+        serde_json::to_string(&settings)?
+    );
+    let etag = format!("\"{}\"", content_hash(inputs.as_bytes()));
+
+    conditional(headers, etag, || async {
+        let comparison = compare_steps(
+            &storage.blob(&left_image_hash).await?.data,
+            &storage.blob(&right_image_hash).await?.data,
+            &ignore_ranges,
+            &settings,
+            matches!(view, DiffView::SsimMap),
+        )
+        .await?;
+        let png = match view {
+            DiffView::Diff => comparison.diff_png()?,
+            DiffView::SsimMap => comparison.ssim_png()?,
+        };
+        image("image/png", png)
+    })
+    .await
+}
+
+/// A small PNG of the screenshot for previews, generated on first request and kept from then on
+async fn thumbnail(
+    State(db): State<Pool<Sqlite>>,
+    Extension(storage): Extension<Storage>,
+    PathParams(step_id): PathParams<i64>,
+    headers: HeaderMap,
+) -> HttpResult<Response> {
+    let (image_hash, _) = get_step_image_hash_and_test_case_id(step_id, &db).await?;
+
+    conditional(&headers, format!("\"{image_hash}-thumbnail\""), || async {
+        if let Some(data) = get_thumbnail(&db, &image_hash).await? {
+            return image("image/png", data);
+        }
+        let data = thumbnail_png(&storage.blob(&image_hash).await?.data)?;
+        insert_thumbnail(&db, &image_hash, &data).await?;
+        image("image/png", data)
+    })
+    .await
+}
+
+pub fn router(db: Pool<Sqlite>) -> Router {
+    Router::new()
+        .route("/steps/:step_id", get(step_image))
+        .route("/thumbnails/:step_id", get(thumbnail))
+        .route("/diffs/:left_step_id/:right_step_id", get(diff_image))
+        .route("/ssim/:left_step_id/:right_step_id", get(ssim_map))
+        .with_state(db)
+}
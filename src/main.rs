@@ -1,23 +1,66 @@
 #![deny(clippy::unwrap_used)]
 
 pub mod api;
+pub mod cli;
+pub mod config;
 pub mod db;
 pub mod error;
+pub mod events;
 pub mod frontend;
+pub mod images;
 pub mod models;
+pub mod retention;
 pub mod services;
+pub mod storage;
 
+use axum::Extension;
 use axum::Router;
+use clap::Parser;
+use clap::Subcommand;
+use events::Events;
 use frontend::pages;
+use models::comparison_settings::ComparisonSettings;
+use retention::RetentionPolicy;
 use std::net::SocketAddr;
+use storage::Storage;
 
 use dotenvy_macro::dotenv;
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::SqlitePool;
 use std::str::FromStr;
 
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Start the server, the default when no command is given
+    Serve,
+    Upload(cli::upload::UploadArgs),
+    Compare(cli::compare::CompareArgs),
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    match Args::parse().command {
+        None | Some(Command::Serve) => serve().await,
+        Some(Command::Upload(args)) => cli::upload::run(args).await,
+        Some(Command::Compare(args)) => match cli::compare::run(args).await {
+            Ok(true) => Ok(()),
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                eprintln!("Error: {err:?}");
+                std::process::exit(2)
+            }
+        },
+    }
+}
+
+async fn serve() -> anyhow::Result<()> {
     dotenvy::dotenv()?;
     pretty_env_logger::init();
 
@@ -25,13 +68,30 @@ async fn main() -> anyhow::Result<()> {
     let db = SqlitePool::connect_with(options).await?;
 
     sqlx::migrate!().run(&db).await?;
+    let storage = Storage::from_env(db.clone())?;
+    let comparison_settings = ComparisonSettings::from_env()?;
+    let (migrated, unreadable) = db::migrate_inline_images(&db, &storage).await?;
+    if migrated > 0 {
+        log::info!("moved {migrated} inlined screenshots into the blob store");
+    }
+    if unreadable > 0 {
+        log::warn!("left out {unreadable} inlined screenshots that could not be decoded");
+    }
+
+    if let Some(policy) = RetentionPolicy::from_env()? {
+        retention::spawn(db.clone(), storage.clone(), policy);
+    }
 
     let app = Router::new()
         .nest("/", pages::index::router(db.clone()))
         .nest("/runs", pages::runs::router(db.clone()))
         .nest("/steps", pages::steps::router(db.clone()))
         .nest("/api", api::router(db.clone()))
-        .nest("/dist", axum_static::static_router("dist"));
+        .nest("/images", images::router(db.clone()))
+        .nest("/dist", axum_static::static_router("dist"))
+        .layer(Extension(Events::default()))
+        .layer(Extension(storage))
+        .layer(Extension(comparison_settings));
 
     let addr = SocketAddr::from_str(dotenv!("ADDRESS"))?;
     println!("listening on http://{addr}");
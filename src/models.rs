@@ -1,4 +1,12 @@
+pub mod blob;
+pub mod capture_metadata;
+pub mod comparison;
+pub mod comparison_settings;
+pub mod conflict_policy;
+pub mod ingest_event;
 pub mod run;
+pub mod run_metadata;
+pub mod run_status;
 pub mod side;
 pub mod step;
 pub mod tag;
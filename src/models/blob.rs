@@ -0,0 +1,25 @@
+use sha2::Digest;
+use sha2::Sha256;
+
+/// A screenshot as stored, keyed by the hash of its bytes so that identical ones are kept once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub hash: String,
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+impl Blob {
+    pub fn new(mime: String, data: Vec<u8>) -> Blob {
+        Blob {
+            hash: content_hash(&data),
+            mime,
+            data,
+        }
+    }
+}
+
+/// Hex SHA-256, the key of a blob
+pub fn content_hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
@@ -0,0 +1,57 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// How a screenshot was taken, as reported by the uploader
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CaptureMetadata {
+    pub viewport_width: Option<u32>,
+    pub viewport_height: Option<u32>,
+    pub device_pixel_ratio: Option<f64>,
+    pub browser: Option<String>,
+    pub browser_version: Option<String>,
+    pub os: Option<String>,
+}
+
+/// Capture metadata together with what the server read from the image itself
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct StepCapture {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    #[serde(flatten)]
+    pub metadata: CaptureMetadata,
+}
+
+fn size(width: Option<u32>, height: Option<u32>) -> Option<String> {
+    match (width, height) {
+        (Some(width), Some(height)) => Some(format!("{width}×{height}")),
+        (Some(width), None) => Some(format!("{width}×?")),
+        (None, Some(height)) => Some(format!("?×{height}")),
+        (None, None) => None,
+    }
+}
+
+impl StepCapture {
+    /// Label and display value of every field, `None` where nothing is known
+    pub fn fields(&self) -> Vec<(&'static str, Option<String>)> {
+        let CaptureMetadata {
+            viewport_width,
+            viewport_height,
+            device_pixel_ratio,
+            browser,
+            browser_version,
+            os,
+        } = &self.metadata;
+
+        vec![
+            ("Image size", size(self.width, self.height)),
+            ("Viewport", size(*viewport_width, *viewport_height)),
+            (
+                "Device pixel ratio",
+                device_pixel_ratio.map(|ratio| ratio.to_string()),
+            ),
+            ("Browser", browser.clone()),
+            ("Browser version", browser_version.clone()),
+            ("OS", os.clone()),
+        ]
+    }
+}
@@ -0,0 +1,98 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize, strum::Display, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TestCaseStatus {
+    /// Only in the right run
+    Added,
+    /// Only in the left run
+    Removed,
+    /// In both runs under the same name
+    Matched,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, strum::Display, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Added,
+    Removed,
+    Changed,
+    Unchanged,
+}
+
+/// How the right run differs from the left one
+#[derive(Debug, Clone, Serialize)]
+pub struct RunComparison {
+    pub left_run_id: i64,
+    pub right_run_id: i64,
+    pub contains_changes: bool,
+    pub test_cases: Vec<TestCaseComparison>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TestCaseComparison {
+    pub name: String,
+    pub status: TestCaseStatus,
+    pub left_test_case_id: Option<i64>,
+    pub right_test_case_id: Option<i64>,
+    pub contains_changes: bool,
+    pub steps: Vec<StepPair>,
+}
+
+/// A step of either run, paired with its namesake from the other one when there is one
+#[derive(Debug, Clone, Serialize)]
+pub struct StepPair {
+    /// Names of the parent steps, outermost first
+    pub path: Vec<String>,
+    pub name: String,
+    pub status: StepStatus,
+    pub left_step_id: Option<i64>,
+    pub right_step_id: Option<i64>,
+    /// Only known when both sides have a screenshot, from 0 to 100
+    pub diff_percentage: Option<f64>,
+    /// Only set when both sides have a screenshot and their sizes differ
+    pub size_mismatch: Option<SizeMismatch>,
+    /// Structural similarity, 1 for identical screenshots. Only known when both sides have a
+    /// screenshot and `min_ssim` is set.
+    pub ssim: Option<f64>,
+}
+
+/// The sizes of two screenshots that don't have the same dimensions.
+/// Their diff covers both, wherever only one of them reaches counts as changed.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct SizeMismatch {
+    pub left_width: u32,
+    pub left_height: u32,
+    pub right_width: u32,
+    pub right_height: u32,
+    /// How much wider the right screenshot is, negative when it is narrower
+    pub width_difference: i64,
+    /// How much taller the right screenshot is, negative when it is shorter
+    pub height_difference: i64,
+}
+
+impl SizeMismatch {
+    /// `None` when both are the same size
+    pub fn between(left: (u32, u32), right: (u32, u32)) -> Option<Self> {
+        (left != right).then(|| SizeMismatch {
+            left_width: left.0,
+            left_height: left.1,
+            right_width: right.0,
+            right_height: right.1,
+            width_difference: i64::from(right.0) - i64::from(left.0),
+            height_difference: i64::from(right.1) - i64::from(left.1),
+        })
+    }
+}
+
+impl std::fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}×{} → {}×{}",
+            self.left_width, self.left_height, self.right_width, self.right_height
+        )
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use strum::EnumString;
+
+use crate::config::env_var;
+use crate::error::ErrorKind;
+
+/// How the pixels of two screenshots are compared
+#[derive(
+    Debug, Clone, Copy, Default, EnumString, Serialize, Deserialize, strum::Display, PartialEq, Eq,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DiffMethod {
+    /// Sums up the raw RGBA differences, the diff percentage weighs them by how far they go
+    #[default]
+    Pixel,
+    /// Compares colors the way the eye perceives them and leaves out anti-aliased pixels,
+    /// the diff percentage is the share of pixels that changed
+    Perceptual,
+}
+
+/// How different two screenshots of a step may be before the step counts as changed.
+///
+/// Test cases, run tags and the server each set some of these, whatever a level leaves out
+/// falls back to the next broader one, see [`ComparisonSettings::or`].
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct ComparisonSettings {
+    /// Largest difference of an RGBA channel, from 0 to 255, that still counts as the same color
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_tolerance: Option<u8>,
+    /// Diff percentage, from 0 to 100, up to which a step still counts as unchanged.
+    /// What it measures depends on `method`: for `pixel` the channel differences weighted by how
+    /// far they go, for `perceptual` the share of pixels that changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<DiffMethod>,
+    /// SSIM, from 0 to 1, below which a step counts as changed.
+    /// When set it decides in place of `threshold`, which suits gradients, shadows and photos better.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_ssim: Option<f64>,
+}
+
+impl ComparisonSettings {
+    /// The server-wide defaults, `DIFF_COLOR_TOLERANCE`, `DIFF_THRESHOLD`, `DIFF_METHOD` and `DIFF_MIN_SSIM`
+    pub fn from_env() -> Result<ComparisonSettings> {
+        let settings = ComparisonSettings {
+            color_tolerance: env_var("DIFF_COLOR_TOLERANCE")?,
+            threshold: env_var("DIFF_THRESHOLD")?,
+            method: env_var("DIFF_METHOD")?,
+            min_ssim: env_var("DIFF_MIN_SSIM")?,
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(threshold) = self.threshold {
+            if !(0.0..=100.0).contains(&threshold) {
+                return Err(ErrorKind::Validation
+                    .error("`threshold` must be a percentage between 0 and 100"));
+            }
+        }
+        if let Some(min_ssim) = self.min_ssim {
+            if !(0.0..=1.0).contains(&min_ssim) {
+                return Err(ErrorKind::Validation.error("`min_ssim` must be between 0 and 1"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps what `self` sets and takes the rest from `fallback`
+    pub fn or(self, fallback: ComparisonSettings) -> ComparisonSettings {
+        ComparisonSettings {
+            color_tolerance: self.color_tolerance.or(fallback.color_tolerance),
+            threshold: self.threshold.or(fallback.threshold),
+            method: self.method.or(fallback.method),
+            min_ssim: self.min_ssim.or(fallback.min_ssim),
+        }
+    }
+
+    /// The most lenient value of every field, for runs with several configured tags.
+    /// Perceptual comparisons ignore more noise, so they win over pixel ones.
+    ///
+    /// A threshold only means something together with its method, so the threshold is the
+    /// largest one of the settings that use the winning method or leave the method open.
+    pub fn loosest(all: impl IntoIterator<Item = ComparisonSettings>) -> ComparisonSettings {
+        let all: Vec<ComparisonSettings> = all.into_iter().collect();
+        let method = [DiffMethod::Perceptual, DiffMethod::Pixel]
+            .into_iter()
+            .find(|method| all.iter().any(|settings| settings.method == Some(*method)));
+
+        ComparisonSettings {
+            color_tolerance: all
+                .iter()
+                .filter_map(|settings| settings.color_tolerance)
+                .max(),
+            threshold: all
+                .iter()
+                .filter(|settings| settings.method.is_none() || settings.method == method)
+                .filter_map(|settings| settings.threshold)
+                .reduce(f64::max),
+            method,
+            min_ssim: all
+                .iter()
+                .filter_map(|settings| settings.min_ssim)
+                .reduce(f64::min),
+        }
+    }
+
+    pub fn color_tolerance(&self) -> u8 {
+        self.color_tolerance.unwrap_or(0)
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold.unwrap_or(0.0)
+    }
+
+    pub fn method(&self) -> DiffMethod {
+        self.method.unwrap_or_default()
+    }
+}
@@ -0,0 +1,32 @@
+use serde::Deserialize;
+use serde::Serialize;
+use strum::EnumString;
+
+/// What ingestion does when a step (or test case) with the same name already exists
+/// and the upload differs from what is stored
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    EnumString,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    PartialEq,
+    Eq,
+    Hash,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Fail the upload with a conflict error, for test cases the first ignore areas and
+    /// comparison settings are kept
+    #[default]
+    Reject,
+    /// Replace the stored screenshot (or ignore areas) with the uploaded one
+    Overwrite,
+    /// Keep the stored screenshot as an earlier attempt and make the upload current,
+    /// for test cases the ignore areas are merged
+    KeepBoth,
+}
@@ -0,0 +1,29 @@
+use serde::Serialize;
+
+use super::run_status::RunStatus;
+
+/// Something ingestion changed, published to `/api/events` subscribers
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IngestEvent {
+    RunAdded {
+        run_id: i64,
+        name: String,
+    },
+    TestCaseAdded {
+        run_id: i64,
+        test_case_id: i64,
+        name: String,
+    },
+    /// Also sent when a step gets a new screenshot
+    StepAdded {
+        run_id: i64,
+        test_case_id: i64,
+        step_id: i64,
+        name: String,
+    },
+    RunFinalized {
+        run_id: i64,
+        status: RunStatus,
+    },
+}
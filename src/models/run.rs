@@ -1,12 +1,20 @@
 use chrono::DateTime;
 use chrono::Utc;
+use serde::Serialize;
 
+use super::run_metadata::RunMetadata;
+use super::run_status::RunStatus;
 use super::tag::Tag;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Run {
     pub id: i64,
     pub name: String,
     pub created_at: DateTime<Utc>,
     pub tags: Vec<Tag>,
+    pub status: RunStatus,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Kept forever by retention
+    pub pinned: bool,
+    pub metadata: RunMetadata,
 }
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::error::ErrorKind;
+
+/// Where a run came from, as reported by the uploader
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RunMetadata {
+    pub vcs_revision: Option<String>,
+    pub branch: Option<String>,
+    pub pull_request: Option<i64>,
+    pub ci_build_url: Option<String>,
+    #[serde(default)]
+    pub environment: BTreeMap<String, String>,
+}
+
+impl RunMetadata {
+    /// Fields set in `other` win, environment entries are merged key by key
+    pub fn merge(&mut self, other: RunMetadata) {
+        let RunMetadata {
+            vcs_revision,
+            branch,
+            pull_request,
+            ci_build_url,
+            environment,
+        } = other;
+
+        self.vcs_revision = vcs_revision.or(self.vcs_revision.take());
+        self.branch = branch.or(self.branch.take());
+        self.pull_request = pull_request.or(self.pull_request);
+        self.ci_build_url = ci_build_url.or(self.ci_build_url.take());
+        self.environment.extend(environment);
+    }
+
+    /// The build URL ends up as a link, so only web URLs are taken
+    pub fn validate(&self) -> Result<()> {
+        if let Some(ci_build_url) = &self.ci_build_url {
+            let lowercase = ci_build_url.to_ascii_lowercase();
+            if !lowercase.starts_with("http://") && !lowercase.starts_with("https://") {
+                return Err(ErrorKind::Validation.error("`ci_build_url` must be an http(s) URL"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn short_revision(&self) -> Option<&str> {
+        self.vcs_revision
+            .as_deref()
+            .map(|revision| revision.get(..8).unwrap_or(revision))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == RunMetadata::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_keeps_what_the_later_upload_leaves_out() {
+        let mut metadata = RunMetadata {
+            vcs_revision: Some("abc".to_string()),
+            branch: Some("main".to_string()),
+            pull_request: Some(1),
+            ci_build_url: None,
+            environment: BTreeMap::from([
+                ("os".to_string(), "linux".to_string()),
+                ("browser".to_string(), "firefox".to_string()),
+            ]),
+        };
+
+        metadata.merge(RunMetadata {
+            vcs_revision: Some("def".to_string()),
+            branch: None,
+            pull_request: None,
+            ci_build_url: Some("https://ci.example.com/1".to_string()),
+            environment: BTreeMap::from([("browser".to_string(), "chromium".to_string())]),
+        });
+
+        assert_eq!(
+            metadata,
+            RunMetadata {
+                vcs_revision: Some("def".to_string()),
+                branch: Some("main".to_string()),
+                pull_request: Some(1),
+                ci_build_url: Some("https://ci.example.com/1".to_string()),
+                environment: BTreeMap::from([
+                    ("os".to_string(), "linux".to_string()),
+                    ("browser".to_string(), "chromium".to_string()),
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn validate_only_accepts_web_build_urls() {
+        let with_url = |url: &str| RunMetadata {
+            ci_build_url: Some(url.to_string()),
+            ..RunMetadata::default()
+        };
+
+        assert!(with_url("HTTPS://ci.example.com/1").validate().is_ok());
+        assert!(with_url("javascript:alert(1)").validate().is_err());
+    }
+}
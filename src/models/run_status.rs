@@ -0,0 +1,42 @@
+use serde::Deserialize;
+use serde::Serialize;
+use strum::EnumString;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    EnumString,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    InProgress,
+    Completed,
+    Failed,
+    Aborted,
+}
+
+impl RunStatus {
+    pub fn is_finished(&self) -> bool {
+        *self != RunStatus::InProgress
+    }
+
+    /// daisyUI badge modifier used wherever the status is rendered
+    pub fn badge_class(&self) -> &'static str {
+        match self {
+            RunStatus::InProgress => "badge-warning",
+            RunStatus::Completed => "badge-success",
+            RunStatus::Failed => "badge-error",
+            RunStatus::Aborted => "badge-ghost",
+        }
+    }
+}
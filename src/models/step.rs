@@ -2,14 +2,20 @@ use std::fmt::Display;
 
 use chrono::DateTime;
 use chrono::Utc;
+use serde::Serialize;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+use super::tag::Tag;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Step {
     pub id: i64,
     pub name: String,
-    pub data_uri: String,
+    /// Key of the screenshot in the blob store
+    pub image_hash: String,
+    pub attempt: i64,
     pub created_at: DateTime<Utc>,
     pub test_case_id: i64,
+    pub tags: Vec<Tag>,
     pub children_steps: Vec<Step>,
 }
 
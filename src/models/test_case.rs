@@ -1,21 +1,33 @@
 use chrono::DateTime;
 use chrono::Utc;
+use serde::Serialize;
 
+use super::comparison_settings::ComparisonSettings;
 use super::step::Step;
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+use super::tag::Tag;
+
+/// Rectangle given by its top-left and bottom-right corners, inclusive
+pub type IgnoreArea = ((u32, u32), (u32, u32));
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct TestCase {
     pub id: i64,
     pub run_id: i64,
     pub name: String,
-    pub ignore_areas: Vec<((u32, u32), (u32, u32))>,
+    pub ignore_areas: Vec<IgnoreArea>,
+    pub comparison_settings: ComparisonSettings,
     pub created_at: DateTime<Utc>,
+    pub tags: Vec<Tag>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct TestCaseWithSteps {
     pub id: i64,
     pub run_id: i64,
     pub name: String,
+    pub ignore_areas: Vec<IgnoreArea>,
+    pub comparison_settings: ComparisonSettings,
     pub created_at: DateTime<Utc>,
+    pub tags: Vec<Tag>,
     pub steps: Vec<Step>,
 }
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use sqlx::Pool;
+use sqlx::Sqlite;
+
+use crate::config::env_var;
+use crate::db::delete_run;
+use crate::db::get_runs;
+use crate::error::ErrorKind;
+use crate::models::run::Run;
+use crate::storage::Storage;
+
+/// Which runs the background job removes, read from the environment:
+///
+/// - `RETENTION_MAX_AGE_DAYS` removes runs created more than that many days ago
+/// - `RETENTION_KEEP_LAST_PER_TAG` keeps only the newest N runs of every tag,
+///   a run survives as long as it is among the newest N of any one of its tags
+/// - `RETENTION_INTERVAL_SECS` is how often the rules are applied, hourly by default
+///
+/// Pinned runs are never removed, and neither are runs still in progress. Those don't take
+/// up a place among the newest N of a tag either, so a run being uploaded never pushes out
+/// the baseline it is going to be compared with.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    pub interval: Duration,
+    pub max_age: Option<chrono::Duration>,
+    pub keep_last_per_tag: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct PrunedRun {
+    pub id: i64,
+    pub name: String,
+    pub reason: String,
+}
+
+impl RetentionPolicy {
+    /// `None` when no rule is configured, so nothing ever gets pruned
+    pub fn from_env() -> Result<Option<RetentionPolicy>> {
+        let max_age = match env_var::<i64>("RETENTION_MAX_AGE_DAYS")? {
+            Some(days) if days < 1 => {
+                return Err(
+                    ErrorKind::Validation.error("`RETENTION_MAX_AGE_DAYS` must be at least 1")
+                );
+            }
+            Some(days) => Some(
+                days.checked_mul(24 * 60 * 60)
+                    .and_then(|secs| {
+                        chrono::Duration::from_std(Duration::from_secs(secs as u64)).ok()
+                    })
+                    .ok_or_else(|| {
+                        ErrorKind::Validation.error("`RETENTION_MAX_AGE_DAYS` is too large")
+                    })?,
+            ),
+            None => None,
+        };
+        let keep_last_per_tag = env_var("RETENTION_KEEP_LAST_PER_TAG")?;
+        let interval = Duration::from_secs(env_var("RETENTION_INTERVAL_SECS")?.unwrap_or(3600));
+        if interval.is_zero() {
+            return Err(ErrorKind::Validation.error("`RETENTION_INTERVAL_SECS` must be at least 1"));
+        }
+
+        if max_age.is_none() && keep_last_per_tag.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(RetentionPolicy {
+            interval,
+            max_age,
+            keep_last_per_tag,
+        }))
+    }
+
+    fn select<'a>(&self, runs: &'a [Run], now: DateTime<Utc>) -> Vec<(&'a Run, String)> {
+        let mut newest_per_tag: HashSet<i64> = HashSet::new();
+        if let Some(keep_last) = self.keep_last_per_tag {
+            let mut runs_per_tag: BTreeMap<&str, Vec<&Run>> = BTreeMap::new();
+            for run in runs.iter().filter(|run| run.status.is_finished()) {
+                for tag in &run.tags {
+                    runs_per_tag.entry(&tag.value).or_default().push(run);
+                }
+            }
+            for tagged_runs in runs_per_tag.values_mut() {
+                tagged_runs.sort_by_key(|run| std::cmp::Reverse(run.created_at));
+                newest_per_tag.extend(tagged_runs.iter().take(keep_last).map(|run| run.id));
+            }
+        }
+
+        runs.iter()
+            .filter(|run| !run.pinned && run.status.is_finished())
+            .filter_map(|run| {
+                if let Some(max_age) = self.max_age {
+                    if now - run.created_at > max_age {
+                        return Some((run, format!("older than {} days", max_age.num_days())));
+                    }
+                }
+                if let Some(keep_last) = self.keep_last_per_tag {
+                    if !run.tags.is_empty() && !newest_per_tag.contains(&run.id) {
+                        return Some((
+                            run,
+                            format!("not among the newest {keep_last} runs of any of its tags"),
+                        ));
+                    }
+                }
+                None
+            })
+            .collect()
+    }
+}
+
+/// Applies the policy once, returning what was removed
+pub async fn prune(
+    db: &Pool<Sqlite>,
+    storage: &Storage,
+    policy: &RetentionPolicy,
+) -> Result<Vec<PrunedRun>> {
+    let runs = get_runs(db.clone()).await?;
+
+    let mut pruned = vec![];
+    for (run, reason) in policy.select(&runs, Utc::now()) {
+        delete_run(db, storage, run.id).await?;
+        pruned.push(PrunedRun {
+            id: run.id,
+            name: run.name.clone(),
+            reason,
+        });
+    }
+    Ok(pruned)
+}
+
+/// Runs [`prune`] every `policy.interval` for as long as the server lives
+pub fn spawn(db: Pool<Sqlite>, storage: Storage, policy: RetentionPolicy) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(policy.interval);
+        loop {
+            interval.tick().await;
+            match prune(&db, &storage, &policy).await {
+                Ok(pruned) => {
+                    for run in &pruned {
+                        log::info!(
+                            "retention removed run {} `{}`: {}",
+                            run.id,
+                            run.name,
+                            run.reason
+                        );
+                    }
+                    log::info!("retention removed {} runs", pruned.len());
+                }
+                Err(err) => log::error!("retention failed: {err:#}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::run_metadata::RunMetadata;
+    use crate::models::run_status::RunStatus;
+    use crate::models::tag::Tag;
+
+    use super::*;
+
+    fn run(id: i64, days_ago: i64, tags: &[&str], pinned: bool) -> Run {
+        run_with_status(id, days_ago, tags, pinned, RunStatus::Completed)
+    }
+
+    fn run_with_status(
+        id: i64,
+        days_ago: i64,
+        tags: &[&str],
+        pinned: bool,
+        status: RunStatus,
+    ) -> Run {
+        Run {
+            id,
+            name: format!("run {id}"),
+            created_at: now() - chrono::Duration::days(days_ago),
+            tags: tags
+                .iter()
+                .enumerate()
+                .map(|(index, value)| Tag {
+                    id: index as i64,
+                    value: value.to_string(),
+                })
+                .collect(),
+            status,
+            finished_at: None,
+            pinned,
+            metadata: RunMetadata::default(),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).expect("valid timestamp")
+    }
+
+    fn selected_ids(policy: &RetentionPolicy, runs: &[Run]) -> Vec<i64> {
+        policy
+            .select(runs, now())
+            .into_iter()
+            .map(|(run, _)| run.id)
+            .collect()
+    }
+
+    #[test]
+    fn select_removes_runs_past_the_max_age_unless_pinned() {
+        let policy = RetentionPolicy {
+            interval: Duration::from_secs(3600),
+            max_age: Some(chrono::Duration::days(30)),
+            keep_last_per_tag: None,
+        };
+        let runs = [
+            run(1, 40, &[], false),
+            run(2, 40, &[], true),
+            run(3, 10, &[], false),
+        ];
+
+        assert_eq!(selected_ids(&policy, &runs), [1]);
+        assert_eq!(policy.select(&runs, now())[0].1, "older than 30 days");
+    }
+
+    #[test]
+    fn select_keeps_runs_that_are_among_the_newest_of_any_tag() {
+        let policy = RetentionPolicy {
+            interval: Duration::from_secs(3600),
+            max_age: None,
+            keep_last_per_tag: Some(1),
+        };
+        let runs = [
+            run(1, 3, &["nightly"], false),
+            run(2, 2, &["nightly", "release"], false),
+            run(3, 1, &["nightly"], false),
+            run(4, 5, &[], false),
+            run(5, 4, &["nightly"], true),
+        ];
+
+        assert_eq!(selected_ids(&policy, &runs), [1]);
+    }
+
+    #[test]
+    fn select_leaves_runs_in_progress_alone() {
+        let policy = RetentionPolicy {
+            interval: Duration::from_secs(3600),
+            max_age: Some(chrono::Duration::days(30)),
+            keep_last_per_tag: Some(1),
+        };
+        let runs = [
+            run(1, 2, &["nightly"], false),
+            run_with_status(2, 1, &["nightly"], false, RunStatus::InProgress),
+            run_with_status(3, 40, &[], false, RunStatus::InProgress),
+        ];
+
+        assert!(selected_ids(&policy, &runs).is_empty());
+    }
+}
@@ -1,85 +1,528 @@
+pub mod perceptual;
+pub mod ssim;
+
 use std::cmp::max;
 use std::cmp::Ordering;
 use std::io::Cursor;
 
+use anyhow::Context;
 use anyhow::Result;
 use base64::Engine;
+use image::imageops::FilterType;
 use image::DynamicImage;
 use image::GenericImage;
 use image::GenericImageView;
 use image::ImageFormat;
-use image::ImageOutputFormat;
+use image::ImageReader;
+use sqlx::Pool;
+use sqlx::Sqlite;
+
+use crate::db::get_case_with_steps;
+use crate::db::get_run;
+use crate::db::get_run_tag_comparison_settings;
+use crate::db::get_run_test_cases;
+use crate::db::get_step_capture;
+use crate::db::get_step_image_hash_and_test_case_id;
+use crate::db::set_blob_dimensions;
+use crate::error::ErrorKind;
+use crate::models::capture_metadata::StepCapture;
+use crate::models::comparison::RunComparison;
+use crate::models::comparison::SizeMismatch;
+use crate::models::comparison::StepPair;
+use crate::models::comparison::StepStatus;
+use crate::models::comparison::TestCaseComparison;
+use crate::models::comparison::TestCaseStatus;
+use crate::models::comparison_settings::ComparisonSettings;
+use crate::models::comparison_settings::DiffMethod;
+use crate::models::step::Step;
+use crate::models::test_case::IgnoreArea;
+use crate::models::test_case::TestCase;
+use crate::models::test_case::TestCaseWithSteps;
+use crate::storage::ImageSource;
+use perceptual::perceptual_diff;
+use ssim::ssim;
+use ssim::Ssim;
 
-fn data_uri_to_dyn_img(data_uri: &str) -> Result<DynamicImage> {
+const THUMBNAIL_WIDTH: u32 = 240;
+const THUMBNAIL_MAX_HEIGHT: u32 = 480;
+
+/// Splits a base64 data URI into its MIME type and decoded bytes
+pub fn data_uri_to_bytes(data_uri: &str) -> Result<(String, Vec<u8>)> {
     // Split the URI to separate the metadata from the actual encoded data
-    let split: Vec<&str> = data_uri.split(',').collect();
-    if split.len() != 2 {
-        panic!("Invalid data URI");
-    }
-    let data = split[1];
+    let Some((meta, data)) = data_uri.split_once(',') else {
+        return Err(ErrorKind::Validation.error("invalid data URI"));
+    };
 
     // Decode the base64 portion
-    let decoded = &base64::engine::general_purpose::STANDARD
-        .decode(data)
-        .expect("Decoding base64 failed");
+    let decoded = base64::engine::general_purpose::STANDARD.decode(data)?;
 
-    // Create a cursor for the byte slice, because the image crate needs a reader.
-    let cursor = Cursor::new(decoded);
-    // Use the reader to load the image and convert it into a DynamicImage.
-    // It automatically detects the format (PNG in this case).
-    Ok(image::load(cursor, ImageFormat::PNG)?)
+    let claimed = meta.trim_start_matches("data:").trim_end_matches(";base64");
+    Ok((image_mime(claimed, &decoded), decoded))
 }
 
-pub async fn compare_steps(
-    left_step_id: &str,
-    right_step_id: &str,
-    ignore_ranges: &[((u32, u32), (u32, u32))],
-) -> Result<(bool, String)> {
-    let l_img = data_uri_to_dyn_img(left_step_id)?;
-    let r_img = data_uri_to_dyn_img(right_step_id)?;
+/// The MIME type of a screenshot, trusting its bytes over the one it came with.
+/// Some uploaders put made up MIME types like `@file/png` in there or label JPEGs as PNGs.
+pub fn image_mime(claimed: &str, bytes: &[u8]) -> String {
+    match image::guess_format(bytes) {
+        Ok(format) => format.to_mime_type().to_string(),
+        Err(_) if claimed.starts_with("image/") => claimed.to_string(),
+        Err(_) => "application/octet-stream".to_string(),
+    }
+}
 
-    let (f, out_img) = subtract_image(&l_img, &r_img, ignore_ranges);
-    let contains_changes = f.total_cmp(&0.0_f64) == Ordering::Greater;
+/// Decodes any supported format, recognized by its magic bytes
+fn bytes_to_dyn_img(bytes: &[u8]) -> Result<DynamicImage> {
+    let format = image::guess_format(bytes).map_err(|_| {
+        ErrorKind::Validation
+            .error("unsupported image format, expected PNG, JPEG, WebP, GIF or BMP")
+    })?;
+    Ok(image::load_from_memory_with_format(bytes, format)?)
+}
+
+/// Pixel size of an image, from its header alone
+pub fn image_dimensions(bytes: &[u8]) -> Result<(u32, u32)> {
+    Ok(ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_dimensions()?)
+}
+
+/// [`get_step_capture`], reading the size from the image if it wasn't recorded when it was stored
+pub async fn step_capture(
+    db: &Pool<Sqlite>,
+    images: &impl ImageSource,
+    step_id: i64,
+) -> Result<StepCapture> {
+    let mut capture = get_step_capture(db, step_id).await?;
+    if capture.width.is_none() || capture.height.is_none() {
+        let (image_hash, _) = get_step_image_hash_and_test_case_id(step_id, db).await?;
+        let dimensions = image_dimensions(&images.image(&image_hash).await?)?;
+        set_blob_dimensions(db, &image_hash, dimensions).await?;
+        (capture.width, capture.height) = (Some(dimensions.0), Some(dimensions.1));
+    }
+    Ok(capture)
+}
 
+/// Rejects screenshots that could not be compared later on
+pub fn validate_step_image(bytes: &[u8]) -> Result<()> {
+    bytes_to_dyn_img(bytes).context("screenshot is not a valid image")?;
+    Ok(())
+}
+
+pub struct StepComparison {
+    pub contains_changes: bool,
+    /// How much of the compared area differs, from 0 to 100
+    pub diff_percentage: f64,
+    pub diff_image: DynamicImage,
+    /// Screenshots of different sizes always count as changed
+    pub size_mismatch: Option<SizeMismatch>,
+    /// Only computed when asked for or needed for the verdict, see [`compare_steps`]
+    pub ssim: Option<Ssim>,
+}
+
+impl StepComparison {
+    pub fn diff_png(&self) -> Result<Vec<u8>> {
+        dyn_img_to_png(&self.diff_image)
+    }
+
+    pub fn ssim_png(&self) -> Result<Vec<u8>> {
+        let ssim = self
+            .ssim
+            .as_ref()
+            .context("the comparison was made without SSIM")?;
+        dyn_img_to_png(&ssim.map())
+    }
+}
+
+fn dyn_img_to_png(img: &DynamicImage) -> Result<Vec<u8>> {
     // We will write the image data to a byte vector in PNG format.
     let mut bytes: Vec<u8> = Vec::new();
-    out_img.write_to(&mut bytes, ImageOutputFormat::PNG)?;
+    img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+    Ok(bytes)
+}
 
-    // Now, we encode these bytes into a base64 string.
-    let base64_string = base64::engine::general_purpose::STANDARD.encode(bytes);
-    Ok((
-        contains_changes,
-        format!("data:image/png;base64,{base64_string}"),
+/// Shrinks a screenshot to [`THUMBNAIL_WIDTH`] and cuts it off below [`THUMBNAIL_MAX_HEIGHT`],
+/// so that the top of a full-page capture stays legible instead of becoming a sliver
+pub fn thumbnail_png(bytes: &[u8]) -> Result<Vec<u8>> {
+    let img = bytes_to_dyn_img(bytes)?;
+    let img = if img.width() > THUMBNAIL_WIDTH {
+        img.resize(THUMBNAIL_WIDTH, u32::MAX, FilterType::Triangle)
+    } else {
+        img
+    };
+    let height = img.height().min(THUMBNAIL_MAX_HEIGHT);
+    dyn_img_to_png(&img.crop_imm(0, 0, img.width(), height))
+}
+
+/// What applies between two runs before their test cases have a say:
+/// the settings of the right run's tags, then those of the left run's tags, then `global`
+pub async fn run_comparison_settings(
+    db: &Pool<Sqlite>,
+    global: ComparisonSettings,
+    left_run_id: i64,
+    right_run_id: i64,
+) -> Result<ComparisonSettings> {
+    let right =
+        ComparisonSettings::loosest(get_run_tag_comparison_settings(db, right_run_id).await?);
+    let left = ComparisonSettings::loosest(get_run_tag_comparison_settings(db, left_run_id).await?);
+    Ok(right.or(left).or(global))
+}
+
+/// The right test case is the one under review, so its settings win over the left one's
+fn test_case_comparison_settings(
+    left: &ComparisonSettings,
+    right: &ComparisonSettings,
+    defaults: ComparisonSettings,
+) -> ComparisonSettings {
+    right.or(*left).or(defaults)
+}
+
+/// Everything that applies to comparing a step of `left` against one of `right`
+pub async fn step_comparison_settings(
+    db: &Pool<Sqlite>,
+    global: ComparisonSettings,
+    left: &TestCase,
+    right: &TestCase,
+) -> Result<ComparisonSettings> {
+    let defaults = run_comparison_settings(db, global, left.run_id, right.run_id).await?;
+    Ok(test_case_comparison_settings(
+        &left.comparison_settings,
+        &right.comparison_settings,
+        defaults,
     ))
 }
 
-/// Originally taken from img_diff library
+/// SSIM costs about as much as the diff itself, so it is only computed when `min_ssim` decides
+/// on the verdict or `with_ssim` asks for it
+pub async fn compare_steps(
+    left_image: &[u8],
+    right_image: &[u8],
+    ignore_ranges: &[IgnoreArea],
+    settings: &ComparisonSettings,
+    with_ssim: bool,
+) -> Result<StepComparison> {
+    let l_img = bytes_to_dyn_img(left_image)?;
+    let r_img = bytes_to_dyn_img(right_image)?;
+
+    let (diff_percentage, diff_image) = match settings.method() {
+        DiffMethod::Pixel => {
+            subtract_image(&l_img, &r_img, ignore_ranges, settings.color_tolerance())
+        }
+        DiffMethod::Perceptual => {
+            perceptual_diff(&l_img, &r_img, ignore_ranges, settings.color_tolerance())
+        }
+    };
+    let ssim =
+        (with_ssim || settings.min_ssim.is_some()).then(|| ssim(&l_img, &r_img, ignore_ranges));
+    let size_mismatch = SizeMismatch::between(l_img.dimensions(), r_img.dimensions());
+    let contains_changes = size_mismatch.is_some()
+        || match (settings.min_ssim, &ssim) {
+            (Some(min_ssim), Some(ssim)) => ssim.score < min_ssim,
+            _ => diff_percentage.total_cmp(&settings.threshold()) == Ordering::Greater,
+        };
+
+    Ok(StepComparison {
+        contains_changes,
+        diff_percentage,
+        diff_image,
+        size_mismatch,
+        ssim,
+    })
+}
+
+/// Test cases of two runs, paired up by name
+pub struct TestCaseMatches<T = TestCase> {
+    pub matches: Vec<(T, T)>,
+    pub left_loners: Vec<T>,
+    pub right_loners: Vec<T>,
+}
+
+pub fn match_test_cases<T>(
+    left_cases: Vec<T>,
+    mut right_cases: Vec<T>,
+    name: impl Fn(&T) -> &str,
+) -> TestCaseMatches<T> {
+    let mut matches: Vec<(T, T)> = vec![];
+    let mut left_loners: Vec<T> = vec![];
+
+    for l in left_cases.into_iter() {
+        if let Some(pos) = right_cases.iter().position(|r| name(&l) == name(r)) {
+            let r = right_cases.remove(pos);
+            matches.push((l, r));
+        } else {
+            left_loners.push(l);
+        }
+    }
+
+    TestCaseMatches {
+        matches,
+        left_loners,
+        right_loners: right_cases,
+    }
+}
+
+fn flatten_steps<'a>(steps: &'a [Step], path: &[String], out: &mut Vec<(Vec<String>, &'a Step)>) {
+    for step in steps {
+        out.push((path.to_vec(), step));
+        let mut child_path = path.to_vec();
+        child_path.push(step.name.clone());
+        flatten_steps(&step.children_steps, &child_path, out);
+    }
+}
+
+fn lone_steps(steps: &[Step], status: StepStatus) -> Vec<StepPair> {
+    let mut flat = vec![];
+    flatten_steps(steps, &[], &mut flat);
+    flat.into_iter()
+        .map(|(path, step)| StepPair {
+            path,
+            name: step.name.clone(),
+            status,
+            left_step_id: (status == StepStatus::Removed).then_some(step.id),
+            right_step_id: (status == StepStatus::Added).then_some(step.id),
+            diff_percentage: None,
+            size_mismatch: None,
+            ssim: None,
+        })
+        .collect()
+}
+
+/// Step names are unique within a test case, so steps are paired by name wherever they sit
+async fn pair_steps(
+    left_case: &TestCaseWithSteps,
+    right_case: &TestCaseWithSteps,
+    images: &impl ImageSource,
+    settings: &ComparisonSettings,
+    on_compared: &mut impl FnMut(&str, &StepPair, &StepComparison) -> Result<()>,
+) -> Result<Vec<StepPair>> {
+    let ignore_ranges = [
+        left_case.ignore_areas.as_slice(),
+        right_case.ignore_areas.as_slice(),
+    ]
+    .concat();
+
+    let mut left_flat = vec![];
+    flatten_steps(&left_case.steps, &[], &mut left_flat);
+    let mut right_flat = vec![];
+    flatten_steps(&right_case.steps, &[], &mut right_flat);
+
+    let mut pairs = vec![];
+    for (path, l) in left_flat {
+        let Some(pos) = right_flat.iter().position(|(_, r)| r.name == l.name) else {
+            pairs.push(StepPair {
+                path,
+                name: l.name.clone(),
+                status: StepStatus::Removed,
+                left_step_id: Some(l.id),
+                right_step_id: None,
+                diff_percentage: None,
+                size_mismatch: None,
+                ssim: None,
+            });
+            continue;
+        };
+        let (_, r) = right_flat.remove(pos);
+        let comparison = compare_steps(
+            &images.image(&l.image_hash).await?,
+            &images.image(&r.image_hash).await?,
+            &ignore_ranges,
+            settings,
+            false,
+        )
+        .await
+        .with_context(|| format!("failed to compare steps {} and {}", l.id, r.id))?;
+        let pair = StepPair {
+            path,
+            name: l.name.clone(),
+            status: if comparison.contains_changes {
+                StepStatus::Changed
+            } else {
+                StepStatus::Unchanged
+            },
+            left_step_id: Some(l.id),
+            right_step_id: Some(r.id),
+            diff_percentage: Some(comparison.diff_percentage),
+            size_mismatch: comparison.size_mismatch,
+            ssim: comparison.ssim.as_ref().map(|ssim| ssim.score),
+        };
+        on_compared(&left_case.name, &pair, &comparison)?;
+        pairs.push(pair);
+    }
+    for (path, r) in right_flat {
+        pairs.push(StepPair {
+            path,
+            name: r.name.clone(),
+            status: StepStatus::Added,
+            left_step_id: None,
+            right_step_id: Some(r.id),
+            diff_percentage: None,
+            size_mismatch: None,
+            ssim: None,
+        });
+    }
+    Ok(pairs)
+}
+
+/// Compares every test case and step of the right side against the left one,
+/// `on_compared` sees every pair of screenshots along with their diff
+pub async fn compare_test_cases(
+    left_run_id: i64,
+    right_run_id: i64,
+    left_cases: Vec<TestCaseWithSteps>,
+    right_cases: Vec<TestCaseWithSteps>,
+    images: &impl ImageSource,
+    defaults: ComparisonSettings,
+    mut on_compared: impl FnMut(&str, &StepPair, &StepComparison) -> Result<()>,
+) -> Result<RunComparison> {
+    let TestCaseMatches {
+        matches,
+        left_loners,
+        right_loners,
+    } = match_test_cases(left_cases, right_cases, |test_case| &test_case.name);
+
+    let mut test_cases = vec![];
+
+    for (l, r) in matches {
+        let settings =
+            test_case_comparison_settings(&l.comparison_settings, &r.comparison_settings, defaults);
+        let steps = pair_steps(&l, &r, images, &settings, &mut on_compared).await?;
+        test_cases.push(TestCaseComparison {
+            contains_changes: steps
+                .iter()
+                .any(|step| step.status != StepStatus::Unchanged),
+            name: l.name,
+            status: TestCaseStatus::Matched,
+            left_test_case_id: Some(l.id),
+            right_test_case_id: Some(r.id),
+            steps,
+        });
+    }
+    for l in left_loners {
+        test_cases.push(TestCaseComparison {
+            steps: lone_steps(&l.steps, StepStatus::Removed),
+            name: l.name,
+            status: TestCaseStatus::Removed,
+            left_test_case_id: Some(l.id),
+            right_test_case_id: None,
+            contains_changes: true,
+        });
+    }
+    for r in right_loners {
+        test_cases.push(TestCaseComparison {
+            steps: lone_steps(&r.steps, StepStatus::Added),
+            name: r.name,
+            status: TestCaseStatus::Added,
+            left_test_case_id: None,
+            right_test_case_id: Some(r.id),
+            contains_changes: true,
+        });
+    }
+
+    Ok(RunComparison {
+        left_run_id,
+        right_run_id,
+        contains_changes: test_cases
+            .iter()
+            .any(|test_case| test_case.contains_changes),
+        test_cases,
+    })
+}
+
+async fn get_run_cases_with_steps(
+    db: &Pool<Sqlite>,
+    run_id: i64,
+) -> Result<Vec<TestCaseWithSteps>> {
+    let mut cases = vec![];
+    for test_case in get_run_test_cases(db, run_id).await? {
+        cases.push(get_case_with_steps(db, test_case.id).await?);
+    }
+    Ok(cases)
+}
+
+/// [`compare_test_cases`] for two stored runs, `global` being the server's settings
+pub async fn compare_runs(
+    db: &Pool<Sqlite>,
+    images: &impl ImageSource,
+    global: ComparisonSettings,
+    left_run_id: i64,
+    right_run_id: i64,
+    on_compared: impl FnMut(&str, &StepPair, &StepComparison) -> Result<()>,
+) -> Result<RunComparison> {
+    let left_run = get_run(db, left_run_id).await?;
+    let right_run = get_run(db, right_run_id).await?;
+
+    compare_test_cases(
+        left_run.id,
+        right_run.id,
+        get_run_cases_with_steps(db, left_run.id).await?,
+        get_run_cases_with_steps(db, right_run.id).await?,
+        images,
+        run_comparison_settings(db, global, left_run.id, right_run.id).await?,
+        on_compared,
+    )
+    .await
+}
+
+/// Composites the channel onto a white background, as transparent screenshots are shown
+fn blend(channel: u8, alpha: f64) -> f64 {
+    255.0 + (f64::from(channel) - 255.0) * alpha
+}
+
+fn is_ignored(ignore_ranges: &[IgnoreArea], x: u32, y: u32) -> bool {
+    ignore_ranges
+        .iter()
+        .any(|((x1, y1), (x2, y2))| (*x1..=*x2).contains(&x) && (*y1..=*y2).contains(&y))
+}
+
+/// Originally taken from img_diff library, extended to screenshots of different sizes:
+/// wherever only one of them reaches counts as changed and is marked yellow in the diff.
+/// Channels differing by no more than `color_tolerance` count as equal.
 pub fn subtract_image(
-    a: &DynamicImage,
-    b: &DynamicImage,
-    ignore_ranges: &[((u32, u32), (u32, u32))],
+    left: &DynamicImage,
+    right: &DynamicImage,
+    ignore_ranges: &[IgnoreArea],
+    color_tolerance: u8,
 ) -> (f64, DynamicImage) {
-    let (x_dim, y_dim) = a.dimensions();
+    // Both are aligned at their top left corner on a canvas large enough for either
+    let left = left.to_rgba8();
+    let right = right.to_rgba8();
+    let x_dim = max(left.width(), right.width());
+    let y_dim = max(left.height(), right.height());
     let mut diff_image = DynamicImage::new_rgba8(x_dim, y_dim);
     let mut max_value: f64 = 0.0;
     let mut current_value: f64 = 0.0;
-    'outer: for ((x, y, pixel_a), (_, _, pixel_b)) in a.pixels().zip(b.pixels()) {
-        for ((x1, y1), (x2, y2)) in ignore_ranges {
-            if (*x1..=*x2).contains(&x) && (*y1..=*y2).contains(&y) {
-                diff_image.put_pixel(x, y, image::Rgba([255, 255, 255, 255]));
-                continue 'outer;
-            }
+    let coordinates = (0..y_dim).flat_map(|y| (0..x_dim).map(move |x| (x, y)));
+    for (x, y) in coordinates {
+        if is_ignored(ignore_ranges, x, y) {
+            diff_image.put_pixel(x, y, image::Rgba([255, 255, 255, 255]));
+            continue;
         }
 
+        let (Some(pixel_a), Some(pixel_b)) =
+            (left.get_pixel_checked(x, y), right.get_pixel_checked(x, y))
+        else {
+            // Only one of the screenshots covers this pixel, which is as changed as it gets
+            max_value += 4.0 * 255.0;
+            current_value += 4.0 * 255.0;
+            diff_image.put_pixel(x, y, image::Rgba([255, 255, 0, 255]));
+            continue;
+        };
+
         // TODO(miguelmendes): find a way to avoid groups of 4 algorithm
         max_value += f64::from(max(pixel_a[0], pixel_b[0]));
         max_value += f64::from(max(pixel_a[1], pixel_b[1]));
         max_value += f64::from(max(pixel_a[2], pixel_b[2]));
         max_value += f64::from(max(pixel_a[3], pixel_b[3]));
-        let r = subtract_and_prevent_overflow(pixel_a[0], pixel_b[0]);
-        let g = subtract_and_prevent_overflow(pixel_a[1], pixel_b[1]);
-        let b = subtract_and_prevent_overflow(pixel_a[2], pixel_b[2]);
-        let a = subtract_and_prevent_overflow(pixel_a[3], pixel_b[3]);
+        let tolerate = |difference: u8| {
+            if difference <= color_tolerance {
+                0
+            } else {
+                difference
+            }
+        };
+        let r = tolerate(subtract_and_prevent_overflow(pixel_a[0], pixel_b[0]));
+        let g = tolerate(subtract_and_prevent_overflow(pixel_a[1], pixel_b[1]));
+        let b = tolerate(subtract_and_prevent_overflow(pixel_a[2], pixel_b[2]));
+        let a = tolerate(subtract_and_prevent_overflow(pixel_a[3], pixel_b[3]));
         current_value += f64::from(r);
         current_value += f64::from(g);
         current_value += f64::from(b);
@@ -94,14 +537,59 @@ pub fn subtract_image(
             }),
         );
     }
+    if max_value == 0.0 {
+        // Everything was ignored or fully transparent black on both sides
+        return (0.0, diff_image);
+    }
     (((current_value * 100.0) / max_value), diff_image)
 }
 
 /// taken from img_diff
 fn subtract_and_prevent_overflow(a: u8, b: u8) -> u8 {
-    if a > b {
-        a - b
-    } else {
-        b - a
+    a.abs_diff(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+    use image::RgbaImage;
+
+    use super::*;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba(color)))
+    }
+
+    #[test]
+    fn subtract_image_counts_the_uncovered_part_of_the_canvas_as_changed() {
+        let left = solid(2, 1, [255, 255, 255, 255]);
+        let right = solid(1, 1, [255, 255, 255, 255]);
+
+        let (percentage, diff) = subtract_image(&left, &right, &[], 0);
+
+        assert_eq!(percentage, 50.0);
+        assert_eq!(diff.dimensions(), (2, 1));
+        assert_eq!(diff.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+        assert_eq!(diff.get_pixel(1, 0), Rgba([255, 255, 0, 255]));
+    }
+
+    #[test]
+    fn subtract_image_skips_ignored_areas_of_the_canvas() {
+        let left = solid(2, 1, [255, 255, 255, 255]);
+        let right = solid(1, 1, [255, 255, 255, 255]);
+
+        let (percentage, _) = subtract_image(&left, &right, &[((1, 0), (1, 0))], 0);
+
+        assert_eq!(percentage, 0.0);
+    }
+
+    #[test]
+    fn subtract_image_weighs_differences_beyond_the_color_tolerance() {
+        let left = solid(1, 1, [110, 100, 100, 255]);
+        let right = solid(1, 1, [100, 100, 100, 255]);
+
+        assert_eq!(subtract_image(&left, &right, &[], 10).0, 0.0);
+        let (percentage, _) = subtract_image(&left, &right, &[], 9);
+        assert!((percentage - 1000.0 / 565.0).abs() < 1e-9);
     }
 }
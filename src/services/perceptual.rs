@@ -0,0 +1,190 @@
+//! Perceptual comparison after pixelmatch: colors are compared by their distance in YIQ,
+//! which weighs brightness the way the eye does, and pixels that only differ because
+//! edges were anti-aliased differently are told apart from actual changes.
+
+use std::cmp::max;
+
+use image::DynamicImage;
+use image::GenericImage;
+use image::Rgba;
+use image::RgbaImage;
+
+use super::blend;
+use super::is_ignored;
+use crate::models::test_case::IgnoreArea;
+
+/// YIQ distance between black and white
+const MAX_YIQ_DELTA: f64 = 35215.0;
+
+fn yiq(pixel: &Rgba<u8>) -> (f64, f64, f64) {
+    let alpha = f64::from(pixel[3]) / 255.0;
+    let r = blend(pixel[0], alpha);
+    let g = blend(pixel[1], alpha);
+    let b = blend(pixel[2], alpha);
+    (
+        r * 0.29889531 + g * 0.58662247 + b * 0.11448223,
+        r * 0.59597799 - g * 0.27417610 - b * 0.32180189,
+        r * 0.21147017 - g * 0.52261711 + b * 0.31114694,
+    )
+}
+
+fn color_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f64 {
+    if a == b {
+        return 0.0;
+    }
+    let (y1, i1, q1) = yiq(a);
+    let (y2, i2, q2) = yiq(b);
+    0.5053 * (y1 - y2).powi(2) + 0.299 * (i1 - i2).powi(2) + 0.1957 * (q1 - q2).powi(2)
+}
+
+/// Positive when `a` is brighter than `b`
+fn brightness_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f64 {
+    yiq(a).0 - yiq(b).0
+}
+
+/// The up to 8 pixels around `(x, y)`, and whether it sits on the edge of the image
+fn neighbours(img: &RgbaImage, x: u32, y: u32) -> (bool, Vec<(u32, u32)>) {
+    let x0 = x.saturating_sub(1);
+    let y0 = y.saturating_sub(1);
+    let x2 = (x + 1).min(img.width() - 1);
+    let y2 = (y + 1).min(img.height() - 1);
+    let on_edge = x == x0 || x == x2 || y == y0 || y == y2;
+    let around = (y0..=y2)
+        .flat_map(|ny| (x0..=x2).map(move |nx| (nx, ny)))
+        .filter(|&(nx, ny)| (nx, ny) != (x, y))
+        .collect();
+    (on_edge, around)
+}
+
+/// Whether more than two of the pixels around `(x, y)` have its exact color
+fn has_many_siblings(img: &RgbaImage, x: u32, y: u32) -> bool {
+    let Some(center) = img.get_pixel_checked(x, y) else {
+        return false;
+    };
+    let (on_edge, around) = neighbours(img, x, y);
+    let same = around
+        .into_iter()
+        .filter(|&(nx, ny)| img.get_pixel(nx, ny) == center)
+        .count();
+    usize::from(on_edge) + same > 2
+}
+
+/// Whether `(x, y)` of `img` looks like an anti-aliased edge: it lies between a darker and a
+/// brighter neighbour, and one of those sits in a flat area of both screenshots
+fn is_antialiased(img: &RgbaImage, other: &RgbaImage, x: u32, y: u32) -> bool {
+    let center = img.get_pixel(x, y);
+    let (on_edge, around) = neighbours(img, x, y);
+    let mut zeroes = usize::from(on_edge);
+    let mut darkest = (0.0, None);
+    let mut brightest = (0.0, None);
+    for (nx, ny) in around {
+        let delta = brightness_delta(center, img.get_pixel(nx, ny));
+        if delta == 0.0 {
+            zeroes += 1;
+            if zeroes > 2 {
+                return false;
+            }
+        } else if delta < darkest.0 {
+            darkest = (delta, Some((nx, ny)));
+        } else if delta > brightest.0 {
+            brightest = (delta, Some((nx, ny)));
+        }
+    }
+
+    let (Some(darkest), Some(brightest)) = (darkest.1, brightest.1) else {
+        return false;
+    };
+    [darkest, brightest]
+        .into_iter()
+        .any(|(nx, ny)| has_many_siblings(img, nx, ny) && has_many_siblings(other, nx, ny))
+}
+
+/// Counterpart to [`super::subtract_image`]. Pixels further apart in YIQ than `color_tolerance`
+/// on the 0 to 255 scale count as changed unless they are anti-aliasing, which is marked grey.
+/// The percentage is the share of compared pixels that changed.
+pub fn perceptual_diff(
+    left: &DynamicImage,
+    right: &DynamicImage,
+    ignore_ranges: &[IgnoreArea],
+    color_tolerance: u8,
+) -> (f64, DynamicImage) {
+    let left = left.to_rgba8();
+    let right = right.to_rgba8();
+    let x_dim = max(left.width(), right.width());
+    let y_dim = max(left.height(), right.height());
+    let max_delta = MAX_YIQ_DELTA * (f64::from(color_tolerance) / 255.0).powi(2);
+
+    let mut diff_image = DynamicImage::new_rgba8(x_dim, y_dim);
+    let mut compared: u64 = 0;
+    let mut changed: u64 = 0;
+    for (x, y) in (0..y_dim).flat_map(|y| (0..x_dim).map(move |x| (x, y))) {
+        if is_ignored(ignore_ranges, x, y) {
+            diff_image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            continue;
+        }
+        compared += 1;
+
+        let (Some(pixel_a), Some(pixel_b)) =
+            (left.get_pixel_checked(x, y), right.get_pixel_checked(x, y))
+        else {
+            changed += 1;
+            diff_image.put_pixel(x, y, Rgba([255, 255, 0, 255]));
+            continue;
+        };
+
+        let color = if color_delta(pixel_a, pixel_b) <= max_delta {
+            [255, 255, 255, 255]
+        } else if is_antialiased(&left, &right, x, y) || is_antialiased(&right, &left, x, y) {
+            [220, 220, 220, 255]
+        } else {
+            changed += 1;
+            [0, 255, 255, 255]
+        };
+        diff_image.put_pixel(x, y, Rgba(color));
+    }
+
+    if compared == 0 {
+        return (0.0, diff_image);
+    }
+    (changed as f64 * 100.0 / compared as f64, diff_image)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GenericImageView;
+
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const GREY: Rgba<u8> = Rgba([128, 128, 128, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /// A vertical edge between black and white, with `middle` as the column in between
+    fn edge(middle: Rgba<u8>) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(7, 5, |x, _| match x {
+            0..=2 => BLACK,
+            3 => middle,
+            _ => WHITE,
+        }))
+    }
+
+    #[test]
+    fn perceptual_diff_leaves_out_an_anti_aliased_edge() {
+        let (percentage, diff) = perceptual_diff(&edge(GREY), &edge(WHITE), &[], 0);
+
+        assert_eq!(percentage, 0.0);
+        assert_eq!(diff.get_pixel(3, 2), Rgba([220, 220, 220, 255]));
+    }
+
+    #[test]
+    fn perceptual_diff_counts_a_changed_pixel_in_a_flat_area() {
+        let left = DynamicImage::ImageRgba8(RgbaImage::from_pixel(5, 5, WHITE));
+        let mut right = left.clone();
+        right.put_pixel(2, 2, BLACK);
+
+        let (percentage, diff) = perceptual_diff(&left, &right, &[], 0);
+
+        assert_eq!(percentage, 4.0);
+        assert_eq!(diff.get_pixel(2, 2), Rgba([0, 255, 255, 255]));
+    }
+}
@@ -0,0 +1,178 @@
+//! Structural similarity of two screenshots, block by block on their luma.
+//! Unlike a changed-pixel count it barely moves for noise in gradients, shadows
+//! or photos, but drops where shapes and edges change.
+
+use std::cmp::max;
+
+use image::DynamicImage;
+use image::GenericImage;
+use image::Rgba;
+use image::RgbaImage;
+
+use super::blend;
+use super::is_ignored;
+use crate::models::test_case::IgnoreArea;
+
+/// Side of the square regions the score is computed over
+const BLOCK_SIZE: u32 = 8;
+/// Keep the score stable for flat blocks, as in the original SSIM paper
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+#[derive(Debug, Clone)]
+pub struct Ssim {
+    /// Mean over all blocks, 1 when both screenshots are identical
+    pub score: f64,
+    width: u32,
+    height: u32,
+    /// Row by row, `None` for blocks that are ignored entirely
+    blocks: Vec<Option<f64>>,
+}
+
+fn luma(pixel: &Rgba<u8>) -> f64 {
+    let alpha = f64::from(pixel[3]) / 255.0;
+    0.299 * blend(pixel[0], alpha) + 0.587 * blend(pixel[1], alpha) + 0.114 * blend(pixel[2], alpha)
+}
+
+fn block_ssim(pairs: &[(f64, f64)]) -> f64 {
+    let n = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|(a, _)| a).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+    let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+    for (a, b) in pairs {
+        var_a += (a - mean_a).powi(2);
+        var_b += (b - mean_b).powi(2);
+        covariance += (a - mean_a) * (b - mean_b);
+    }
+    var_a /= n;
+    var_b /= n;
+    covariance /= n;
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a.powi(2) + mean_b.powi(2) + C1) * (var_a + var_b + C2))
+}
+
+/// Both screenshots aligned at their top left corner, a block that only one of them
+/// covers completely has nothing in common. Ignored pixels count as equal.
+pub fn ssim(left: &DynamicImage, right: &DynamicImage, ignore_ranges: &[IgnoreArea]) -> Ssim {
+    let left = left.to_rgba8();
+    let right = right.to_rgba8();
+    let width = max(left.width(), right.width());
+    let height = max(left.height(), right.height());
+
+    let mut blocks = vec![];
+    for block_y in (0..height).step_by(BLOCK_SIZE as usize) {
+        for block_x in (0..width).step_by(BLOCK_SIZE as usize) {
+            blocks.push(compare_block(
+                &left,
+                &right,
+                ignore_ranges,
+                block_x,
+                block_y,
+                width,
+                height,
+            ));
+        }
+    }
+
+    let scored: Vec<f64> = blocks.iter().flatten().copied().collect();
+    let score = if scored.is_empty() {
+        1.0
+    } else {
+        scored.iter().sum::<f64>() / scored.len() as f64
+    };
+    Ssim {
+        score,
+        width,
+        height,
+        blocks,
+    }
+}
+
+fn compare_block(
+    left: &RgbaImage,
+    right: &RgbaImage,
+    ignore_ranges: &[IgnoreArea],
+    block_x: u32,
+    block_y: u32,
+    width: u32,
+    height: u32,
+) -> Option<f64> {
+    let mut pairs = vec![];
+    let mut all_ignored = true;
+    for y in block_y..(block_y + BLOCK_SIZE).min(height) {
+        for x in block_x..(block_x + BLOCK_SIZE).min(width) {
+            let ignored = is_ignored(ignore_ranges, x, y);
+            all_ignored &= ignored;
+            let (Some(pixel_a), Some(pixel_b)) =
+                (left.get_pixel_checked(x, y), right.get_pixel_checked(x, y))
+            else {
+                if ignored {
+                    continue;
+                }
+                return Some(0.0);
+            };
+            let a = luma(pixel_a);
+            pairs.push((a, if ignored { a } else { luma(pixel_b) }));
+        }
+    }
+    if all_ignored || pairs.is_empty() {
+        return None;
+    }
+    Some(block_ssim(&pairs))
+}
+
+impl Ssim {
+    /// Every block shaded by its score, white where it is identical and black where nothing matches
+    pub fn map(&self) -> DynamicImage {
+        let columns = self.width.div_ceil(BLOCK_SIZE);
+        let mut map = DynamicImage::new_rgba8(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let block = (y / BLOCK_SIZE * columns + x / BLOCK_SIZE) as usize;
+                let shade = match self.blocks[block] {
+                    Some(score) => (score.clamp(0.0, 1.0) * 255.0).round() as u8,
+                    None => 255,
+                };
+                map.put_pixel(x, y, Rgba([shade, shade, shade, 255]));
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GenericImageView;
+
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            let value = (x * 16 + y * 4) as u8;
+            Rgba([value, value, value, 255])
+        }))
+    }
+
+    #[test]
+    fn ssim_of_identical_screenshots_is_one() {
+        let image = gradient(16, 16);
+
+        let ssim = ssim(&image, &image, &[]);
+
+        assert!((ssim.score - 1.0).abs() < 1e-12);
+        assert_eq!(ssim.blocks.len(), 4);
+    }
+
+    #[test]
+    fn ssim_block_straddling_the_size_mismatch_scores_zero() {
+        // The second block column holds x 8 to 11, of which only 8 and 9 are in both
+        let ssim = ssim(&gradient(12, 8), &gradient(10, 8), &[]);
+
+        assert_eq!(ssim.blocks.len(), 2);
+        assert!(matches!(ssim.blocks[0], Some(score) if (score - 1.0).abs() < 1e-12));
+        assert_eq!(ssim.blocks[1], Some(0.0));
+        assert!((ssim.score - 0.5).abs() < 1e-12);
+        assert_eq!(ssim.map().get_pixel(9, 0), Rgba([0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,194 @@
+use std::env;
+use std::future::Future;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::Result;
+use bytes::Bytes;
+use object_store::aws::AmazonS3Builder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use sqlx::Pool;
+use sqlx::Sqlite;
+use sqlx::SqliteConnection;
+use sqlx::Transaction;
+use strum::EnumString;
+
+use crate::db::blob_exists;
+use crate::db::get_blob;
+use crate::db::insert_blob;
+use crate::models::blob::Blob;
+use crate::services::image_dimensions;
+
+/// Where comparisons get the screenshots of steps from, by [`crate::models::step::Step::image_hash`]
+pub trait ImageSource {
+    fn image(&self, hash: &str) -> impl Future<Output = Result<Vec<u8>>> + Send;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+enum StorageKind {
+    #[default]
+    Database,
+    Local,
+    S3,
+}
+
+#[derive(Debug, Clone)]
+enum Backend {
+    Database,
+    Objects(Arc<dyn ObjectStore>),
+}
+
+/// Keeps the screenshots, the backend is read from the environment:
+///
+/// - `STORAGE=database`, the default, keeps them in the `blob` table
+/// - `STORAGE=local` writes them as files under `STORAGE_DIR`, `screenshots` by default
+/// - `STORAGE=s3` puts them into the bucket `S3_BUCKET`, `S3_ENDPOINT` points at an
+///   S3-compatible server such as MinIO, credentials and region come from the usual `AWS_*` variables
+///
+/// The `blob` table indexes every screenshot whatever the backend, those stored in it
+/// before switching away from `database` keep being read from there.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    db: Pool<Sqlite>,
+    backend: Backend,
+}
+
+fn key(hash: &str) -> Path {
+    // Spreads the files over directories instead of piling all of them into one
+    match hash.get(..2) {
+        Some(prefix) => Path::from(format!("{prefix}/{hash}")),
+        None => Path::from(hash),
+    }
+}
+
+impl Storage {
+    pub fn from_env(db: Pool<Sqlite>) -> Result<Storage> {
+        let kind = match env::var("STORAGE") {
+            Ok(kind) => StorageKind::from_str(&kind).with_context(|| {
+                format!("invalid `STORAGE`: {kind}, expected database, local or s3")
+            })?,
+            Err(_) => StorageKind::default(),
+        };
+
+        let store: Arc<dyn ObjectStore> = match kind {
+            StorageKind::Database => {
+                return Ok(Storage {
+                    db,
+                    backend: Backend::Database,
+                })
+            }
+            StorageKind::Local => {
+                let dir = PathBuf::from(env::var("STORAGE_DIR").unwrap_or("screenshots".into()));
+                std::fs::create_dir_all(&dir)
+                    .with_context(|| format!("failed to create `{}`", dir.display()))?;
+                Arc::new(LocalFileSystem::new_with_prefix(&dir)?)
+            }
+            StorageKind::S3 => {
+                let bucket = env::var("S3_BUCKET").context("`STORAGE=s3` needs `S3_BUCKET`")?;
+                let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+                if let Ok(endpoint) = env::var("S3_ENDPOINT") {
+                    builder = builder
+                        .with_allow_http(endpoint.starts_with("http://"))
+                        .with_endpoint(endpoint);
+                }
+                Arc::new(builder.build()?)
+            }
+        };
+
+        Ok(Storage {
+            db,
+            backend: Backend::Objects(store),
+        })
+    }
+
+    /// Stores the image unless an identical one already is, indexed in the same transaction as the step.
+    /// Objects it writes are added to `stored`, for [`Storage::commit`] to remove should the
+    /// transaction not go through.
+    pub async fn put(
+        &self,
+        conn: &mut SqliteConnection,
+        blob: &Blob,
+        stored: &mut Vec<String>,
+    ) -> Result<()> {
+        let dimensions = image_dimensions(&blob.data).ok();
+        match &self.backend {
+            Backend::Database => {
+                insert_blob(conn, &blob.hash, &blob.mime, &blob.data, dimensions).await
+            }
+            Backend::Objects(store) => {
+                if blob_exists(&mut *conn, &blob.hash).await? {
+                    return Ok(());
+                }
+                store
+                    .put(&key(&blob.hash), Bytes::from(blob.data.clone()))
+                    .await
+                    .with_context(|| format!("failed to store image {} in {store}", blob.hash))?;
+                stored.push(blob.hash.clone());
+                insert_blob(conn, &blob.hash, &blob.mime, &[], dimensions).await
+            }
+        }
+    }
+
+    /// Commits the transaction `result` was written in, or removes the objects [`Storage::put`]
+    /// stored for it. They go before the transaction is rolled back, while it still keeps other
+    /// uploads from storing the same images.
+    pub async fn commit<T, E>(
+        &self,
+        tx: Transaction<'_, Sqlite>,
+        stored: &[String],
+        result: Result<T, E>,
+    ) -> Result<T, E>
+    where
+        E: From<sqlx::Error>,
+    {
+        let result = match result {
+            Ok(value) => tx.commit().await.map(|_| value).map_err(E::from),
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            self.forget(stored).await;
+        }
+        result
+    }
+
+    /// The image with its MIME type, wherever it is kept
+    pub async fn blob(&self, hash: &str) -> Result<Blob> {
+        let mut blob = get_blob(&self.db, hash).await?;
+        if let Backend::Objects(store) = &self.backend {
+            // Rows stored while the database was the backend hold their data themselves
+            if blob.data.is_empty() {
+                blob.data = store
+                    .get(&key(hash))
+                    .await
+                    .with_context(|| format!("image {hash} not found in {store}"))?
+                    .bytes()
+                    .await?
+                    .to_vec();
+            }
+        }
+        Ok(blob)
+    }
+
+    /// Removes images whose rows are gone, failing only leaves unreachable objects behind
+    pub async fn forget(&self, hashes: &[String]) {
+        let Backend::Objects(store) = &self.backend else {
+            return;
+        };
+        for hash in hashes {
+            if let Err(err) = store.delete(&key(hash)).await {
+                log::warn!("failed to delete image {hash} from {store}: {err}");
+            }
+        }
+    }
+}
+
+impl ImageSource for Storage {
+    async fn image(&self, hash: &str) -> Result<Vec<u8>> {
+        Ok(self.blob(hash).await?.data)
+    }
+}